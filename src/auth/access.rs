@@ -0,0 +1,123 @@
+use serde::{Deserialize, Serialize};
+use std::{fmt, str::FromStr};
+
+pub const REPOSITORY: &str = "repository";
+pub const REGISTRY: &str = "registry";
+
+pub const PULL: &str = "pull";
+pub const PUSH: &str = "push";
+pub const DELETE: &str = "delete";
+pub const ADMIN: &str = "admin";
+
+/// Actions on a resource, as requested by a `scope` and granted by the `access` claim of a token.
+///
+/// See https://docs.docker.com/registry/spec/auth/scope/
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Access {
+    /// The type of the resource, e.g. `repository`.
+    #[serde(rename = "type")]
+    pub resource_type: String,
+
+    /// The name of the resource, e.g. `$repository/$image`.
+    /// A name ending with `/*` covers every image of a repository.
+    pub name: String,
+
+    /// The actions on the resource, e.g. `pull` or `push`. A `*` stands for any action.
+    pub actions: Vec<String>,
+}
+
+impl Access {
+    pub fn new(resource_type: &str, name: &str, actions: &[&str]) -> Self {
+        Self {
+            resource_type: resource_type.to_string(),
+            name: name.to_string(),
+            actions: actions.iter().map(|action| action.to_string()).collect(),
+        }
+    }
+
+    /// Whether the access covers the resource, regardless of the actions.
+    pub fn covers(&self, resource_type: &str, name: &str) -> bool {
+        if self.resource_type != resource_type {
+            return false;
+        }
+        match self.name.strip_suffix("/*") {
+            Some(prefix) => name.strip_prefix(prefix).map_or(false, |rest| rest.starts_with('/')),
+            None => self.name == name,
+        }
+    }
+
+    /// Whether the access allows the action on the resource.
+    pub fn allows(&self, resource_type: &str, name: &str, action: &str) -> bool {
+        self.covers(resource_type, name) && self.actions.iter().any(|granted| granted == action || granted == "*")
+    }
+}
+
+/// Parse a scope of the form `<type>:<name>:<action>[,<action>...]`.
+///
+/// The name may contain a colon, e.g. a hostname with a port, so the type and actions are split off from each end.
+impl FromStr for Access {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (resource_type, rest) = s.split_once(':').ok_or(())?;
+        let (name, actions) = rest.rsplit_once(':').ok_or(())?;
+        if resource_type.is_empty() || name.is_empty() {
+            return Err(());
+        }
+        Ok(Self {
+            resource_type: resource_type.to_string(),
+            name: name.to_string(),
+            actions: actions
+                .split(',')
+                .filter(|action| !action.is_empty())
+                .map(str::to_string)
+                .collect(),
+        })
+    }
+}
+
+impl fmt::Display for Access {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}:{}", self.resource_type, self.name, self.actions.join(","))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse_scope() {
+        let access = "repository:samalba/my-app:pull,push".parse::<Access>().unwrap();
+        assert_eq!(access, Access::new(REPOSITORY, "samalba/my-app", &[PULL, PUSH]));
+        assert_eq!(access.to_string(), "repository:samalba/my-app:pull,push");
+
+        let access = "repository:localhost:5000/samalba/my-app:pull"
+            .parse::<Access>()
+            .unwrap();
+        assert_eq!(access.name, "localhost:5000/samalba/my-app");
+
+        assert!("repository:samalba/my-app".parse::<Access>().is_err());
+        assert!(":samalba/my-app:pull".parse::<Access>().is_err());
+    }
+
+    #[test]
+    fn allow_actions() {
+        let access = Access::new(REPOSITORY, "samalba/my-app", &[PULL]);
+        assert!(access.allows(REPOSITORY, "samalba/my-app", PULL));
+        assert!(!access.allows(REPOSITORY, "samalba/my-app", PUSH));
+        assert!(!access.allows(REPOSITORY, "samalba/other-app", PULL));
+
+        let access = Access::new(REGISTRY, "catalog", &["*"]);
+        assert!(access.allows(REGISTRY, "catalog", "*"));
+        assert!(!access.allows(REPOSITORY, "catalog", PULL));
+    }
+
+    #[test]
+    fn allow_every_image_of_repository() {
+        let access = Access::new(REPOSITORY, "samalba/*", &[PULL, PUSH]);
+        assert!(access.allows(REPOSITORY, "samalba/my-app", PUSH));
+        assert!(!access.allows(REPOSITORY, "samalba-evil/my-app", PULL));
+        assert!(!access.allows(REPOSITORY, "samalba", PULL));
+    }
+}
@@ -0,0 +1,205 @@
+use serde::Serialize;
+use std::collections::HashMap;
+
+use super::access::{self, Access};
+use super::jwt::{self, Audience, Claims, TokenError};
+use super::AuthConfig;
+
+/// How long an access token is valid, in seconds. Clients get a new one for each scope anyway.
+pub const ACCESS_TOKEN_TTL: u64 = 300;
+
+/// How long a refresh token, kept by `docker login`, is valid, in seconds.
+pub const REFRESH_TOKEN_TTL: u64 = 30 * 24 * 60 * 60;
+
+/// The response of the token endpoint, for both the Docker and the OAuth2 flavors.
+///
+/// See https://docs.docker.com/registry/spec/auth/token/#token-response-fields
+#[derive(Debug, Serialize)]
+pub struct TokenResponse {
+    pub token: String,
+    pub access_token: String,
+    pub expires_in: u64,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub refresh_token: Option<String>,
+}
+
+/// The audience of refresh tokens, so that they can't be used as access tokens, and vice versa.
+fn refresh_audience(config: &AuthConfig) -> String {
+    format!("{}:refresh", config.service)
+}
+
+/// Narrow the requested scopes down to what a robot of the repository is allowed.
+///
+/// Robots only get access to images of their own repository. The catalog is granted as pulling every image of it,
+/// which is what it gets filtered by.
+pub fn grant(repository_name: &str, allowed: &[String], requested: &[Access]) -> Vec<Access> {
+    let tenant = Access::new(access::REPOSITORY, &format!("{}/*", repository_name), &[]);
+    let is_allowed = |action: &str| allowed.iter().any(|granted| granted == action || granted == "*");
+
+    let mut granted: Vec<Access> = Vec::new();
+    for scope in requested {
+        let (name, actions): (&str, Vec<&str>) = match scope.resource_type.as_str() {
+            access::REPOSITORY if tenant.covers(access::REPOSITORY, &scope.name) => (
+                &scope.name,
+                scope
+                    .actions
+                    .iter()
+                    .map(String::as_str)
+                    .filter(|action| is_allowed(action))
+                    .collect(),
+            ),
+            access::REGISTRY if scope.name == "catalog" && is_allowed(access::PULL) => {
+                (&tenant.name, vec![access::PULL])
+            }
+            _ => continue,
+        };
+        if !actions.is_empty() {
+            granted.push(Access::new(access::REPOSITORY, name, &actions));
+        }
+    }
+    granted
+}
+
+/// Issue a short-lived access token for the registry to the subject.
+pub fn issue_access_token(
+    config: &AuthConfig,
+    subject: &str,
+    access: Vec<Access>,
+    now: u64,
+) -> Result<String, TokenError> {
+    let claims = Claims {
+        iss: config.issuer.clone(),
+        sub: subject.to_string(),
+        aud: Audience::One(config.service.clone()),
+        exp: now + ACCESS_TOKEN_TTL,
+        nbf: Some(now),
+        iat: Some(now),
+        jti: Some(uuid::Uuid::new_v4().to_string()),
+        access,
+        extra: HashMap::new(),
+    };
+    jwt::sign(&claims, signing_key(config)?)
+}
+
+/// Issue a refresh token, which gets the subject access tokens without its credentials.
+pub fn issue_refresh_token(config: &AuthConfig, subject: &str, now: u64) -> Result<String, TokenError> {
+    let claims = Claims {
+        iss: config.issuer.clone(),
+        sub: subject.to_string(),
+        aud: Audience::One(refresh_audience(config)),
+        exp: now + REFRESH_TOKEN_TTL,
+        nbf: Some(now),
+        iat: Some(now),
+        jti: Some(uuid::Uuid::new_v4().to_string()),
+        access: vec![],
+        extra: HashMap::new(),
+    };
+    jwt::sign(&claims, signing_key(config)?)
+}
+
+/// Verify a refresh token issued by the registry. Whether its subject still exists is up to the caller.
+pub fn verify_refresh_token(config: &AuthConfig, token: &str, now: u64) -> Result<Claims, TokenError> {
+    jwt::verify(token, &config.keys, &config.issuer, &refresh_audience(config), now)
+}
+
+/// The first of the signing keys is in use.
+fn signing_key(config: &AuthConfig) -> Result<&jwt::Jwk, TokenError> {
+    config.signing_keys.keys.first().ok_or(TokenError::UnknownKey)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+    use jwt::{Jwk, JwkSet};
+    use p256::ecdsa::SigningKey;
+
+    const NOW: u64 = 1_700_000_000;
+
+    fn config() -> AuthConfig {
+        let key = SigningKey::from_slice(&[9; 32]).unwrap();
+        let point = key.verifying_key().to_encoded_point(false);
+        let encode = |bytes: &[u8]| URL_SAFE_NO_PAD.encode(bytes);
+        let jwk = Jwk {
+            kty: "EC".to_string(),
+            kid: Some("2023-11".to_string()),
+            alg: Some("ES256".to_string()),
+            crv: Some("P-256".to_string()),
+            x: Some(encode(point.x().unwrap())),
+            y: Some(encode(point.y().unwrap())),
+            n: None,
+            e: None,
+            d: Some(encode(&key.to_bytes())),
+        };
+        AuthConfig {
+            realm: "https://registry.example.com/token".to_string(),
+            service: "registry.example.com".to_string(),
+            issuer: "registry-edge".to_string(),
+            keys: JwkSet {
+                keys: vec![jwk.public()],
+            },
+            signing_keys: JwkSet { keys: vec![jwk] },
+            oidc_providers: Vec::new(),
+            trust_rules: HashMap::new(),
+        }
+    }
+
+    #[test]
+    fn grant_requested_scopes() {
+        let allowed = vec![access::PULL.to_string()];
+        let requested = vec![
+            Access::new(access::REPOSITORY, "samalba/my-app", &[access::PULL, access::PUSH]),
+            Access::new(access::REPOSITORY, "other/my-app", &[access::PULL]),
+            Access::new(access::REPOSITORY, "samalba/my-app", &[access::DELETE]),
+            Access::new(access::REGISTRY, "catalog", &["*"]),
+        ];
+        assert_eq!(
+            grant("samalba", &allowed, &requested),
+            vec![
+                Access::new(access::REPOSITORY, "samalba/my-app", &[access::PULL]),
+                Access::new(access::REPOSITORY, "samalba/*", &[access::PULL]),
+            ]
+        );
+
+        let allowed = vec!["*".to_string()];
+        assert_eq!(
+            grant("samalba", &allowed, &requested[..1]),
+            vec![Access::new(
+                access::REPOSITORY,
+                "samalba/my-app",
+                &[access::PULL, access::PUSH]
+            )]
+        );
+    }
+
+    #[test]
+    fn issue_tokens() {
+        let config = config();
+        let access = vec![Access::new(access::REPOSITORY, "samalba/my-app", &[access::PULL])];
+
+        let token = issue_access_token(&config, "samalba+ci", access.clone(), NOW).unwrap();
+        let claims = jwt::verify(&token, &config.keys, &config.issuer, &config.service, NOW).unwrap();
+        assert_eq!(claims.sub, "samalba+ci");
+        assert_eq!(claims.access, access);
+        assert_eq!(
+            jwt::verify(
+                &token,
+                &config.keys,
+                &config.issuer,
+                &config.service,
+                NOW + ACCESS_TOKEN_TTL + 60
+            )
+            .unwrap_err(),
+            TokenError::Expired
+        );
+        assert!(verify_refresh_token(&config, &token, NOW).is_err());
+
+        let token = issue_refresh_token(&config, "samalba+ci", NOW).unwrap();
+        assert_eq!(verify_refresh_token(&config, &token, NOW).unwrap().sub, "samalba+ci");
+        assert_eq!(
+            jwt::verify(&token, &config.keys, &config.issuer, &config.service, NOW).unwrap_err(),
+            TokenError::InvalidClaim("aud")
+        );
+    }
+}
@@ -0,0 +1,415 @@
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use p256::ecdsa::{
+    signature::{Signer, Verifier},
+    Signature as EcdsaSignature, SigningKey as EcdsaSigningKey, VerifyingKey as EcdsaVerifyingKey,
+};
+use p256::EncodedPoint;
+use rsa::{pkcs1v15, BigUint, RsaPublicKey};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use std::collections::HashMap;
+
+use super::access::Access;
+
+/// Tolerated clock skew between the issuer and the worker, in seconds.
+const LEEWAY: u64 = 60;
+
+/// A public key of an issuer.
+///
+/// See https://www.rfc-editor.org/rfc/rfc7517#section-4
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Jwk {
+    /// `EC` or `RSA`.
+    pub kty: String,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub kid: Option<String>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub alg: Option<String>,
+
+    /// The curve of an `EC` key, only `P-256` is supported.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub crv: Option<String>,
+
+    /// The coordinates of the point of an `EC` key.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub x: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub y: Option<String>,
+
+    /// The modulus and exponent of a `RSA` key.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub n: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub e: Option<String>,
+
+    /// The private scalar of an `EC` key, only known for keys the registry signs with.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub d: Option<String>,
+}
+
+impl Jwk {
+    /// The key without its private part.
+    pub fn public(&self) -> Self {
+        Self {
+            d: None,
+            ..self.clone()
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct JwkSet {
+    pub keys: Vec<Jwk>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Header {
+    pub alg: String,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub kid: Option<String>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub typ: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum Audience {
+    One(String),
+    Many(Vec<String>),
+}
+
+impl Audience {
+    pub fn contains(&self, audience: &str) -> bool {
+        match self {
+            Self::One(one) => one == audience,
+            Self::Many(many) => many.iter().any(|one| one == audience),
+        }
+    }
+}
+
+/// Claims of a registry token.
+///
+/// See https://docs.docker.com/registry/spec/auth/jwt/
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Claims {
+    /// The issuer of the token, which must be trusted by the registry.
+    pub iss: String,
+
+    /// The principal the token has been issued to, e.g. a robot account.
+    pub sub: String,
+
+    /// The service the token is meant for, i.e. the registry.
+    pub aud: Audience,
+
+    /// The expiration time, in seconds since the epoch.
+    pub exp: u64,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub nbf: Option<u64>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub iat: Option<u64>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub jti: Option<String>,
+
+    /// The actions granted on each resource.
+    #[serde(default)]
+    pub access: Vec<Access>,
+
+    /// Any other claim, e.g. the `repository` and `ref` of an OIDC token of a CI job.
+    #[serde(flatten)]
+    pub extra: HashMap<String, serde_json::Value>,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum TokenError {
+    Malformed,
+    UnsupportedAlgorithm(String),
+    UnknownKey,
+    InvalidSignature,
+    Expired,
+    InvalidClaim(&'static str),
+}
+
+/// Verify a token issued by `issuer` for `service`, and not expired at `now` in seconds since the epoch.
+pub fn verify(token: &str, keys: &JwkSet, issuer: &str, service: &str, now: u64) -> Result<Claims, TokenError> {
+    let (_, payload) = verify_signature(token, keys)?;
+    let claims: Claims = serde_json::from_slice(&payload).map_err(|_| TokenError::Malformed)?;
+
+    if claims.iss != issuer {
+        return Err(TokenError::InvalidClaim("iss"));
+    }
+    if !claims.aud.contains(service) {
+        return Err(TokenError::InvalidClaim("aud"));
+    }
+    if claims.exp + LEEWAY <= now {
+        return Err(TokenError::Expired);
+    }
+    if claims.nbf.map_or(false, |nbf| nbf > now + LEEWAY) {
+        return Err(TokenError::InvalidClaim("nbf"));
+    }
+    Ok(claims)
+}
+
+/// Verify the signature of a JWS in the compact serialization against one of the keys, returning its payload.
+///
+/// Only `ES256` and `RS256` are supported.
+pub fn verify_signature(token: &str, keys: &JwkSet) -> Result<(Header, Vec<u8>), TokenError> {
+    let mut parts = token.split('.');
+    let (header, payload, signature) = match (parts.next(), parts.next(), parts.next(), parts.next()) {
+        (Some(header), Some(payload), Some(signature), None) => (header, payload, signature),
+        _ => return Err(TokenError::Malformed),
+    };
+    let signing_input = &token[..header.len() + 1 + payload.len()];
+    let header: Header = serde_json::from_slice(&decode(header)?).map_err(|_| TokenError::Malformed)?;
+    let payload = decode(payload)?;
+    let signature = decode(signature)?;
+
+    let kty = match header.alg.as_str() {
+        "ES256" => "EC",
+        "RS256" => "RSA",
+        alg => return Err(TokenError::UnsupportedAlgorithm(alg.to_string())),
+    };
+    let key = keys
+        .keys
+        .iter()
+        .filter(|key| key.kty == kty)
+        .find(|key| header.kid.is_none() || key.kid == header.kid)
+        .ok_or(TokenError::UnknownKey)?;
+
+    let verified = match kty {
+        "EC" => verify_es256(key, signing_input.as_bytes(), &signature),
+        _ => verify_rs256(key, signing_input.as_bytes(), &signature),
+    };
+    match verified {
+        Some(true) => Ok((header, payload)),
+        Some(false) => Err(TokenError::InvalidSignature),
+        None => Err(TokenError::UnknownKey),
+    }
+}
+
+/// Sign the claims with a private `EC` key, as a JWS in the compact serialization.
+///
+/// The `kid` of the key is put in the header, so that the key can be told apart from others while rotating them.
+pub fn sign<T: Serialize>(claims: &T, key: &Jwk) -> Result<String, TokenError> {
+    let d = decode(key.d.as_deref().ok_or(TokenError::UnknownKey)?)?;
+    let signing_key = EcdsaSigningKey::from_slice(&d).map_err(|_| TokenError::UnknownKey)?;
+
+    let header = Header {
+        alg: "ES256".to_string(),
+        kid: key.kid.clone(),
+        typ: Some("JWT".to_string()),
+    };
+    let header = serde_json::to_vec(&header).map_err(|_| TokenError::Malformed)?;
+    let payload = serde_json::to_vec(claims).map_err(|_| TokenError::Malformed)?;
+    let signing_input = format!("{}.{}", encode(&header), encode(&payload));
+    let signature: EcdsaSignature = signing_key.sign(signing_input.as_bytes());
+    Ok(format!("{}.{}", signing_input, encode(&signature.to_bytes())))
+}
+
+/// Returns `None` if the key itself is invalid.
+fn verify_es256(key: &Jwk, message: &[u8], signature: &[u8]) -> Option<bool> {
+    if key.crv.as_deref() != Some("P-256") {
+        return None;
+    }
+    let x = decode(key.x.as_deref()?).ok()?;
+    let y = decode(key.y.as_deref()?).ok()?;
+    if x.len() != 32 || y.len() != 32 {
+        return None;
+    }
+    let point = EncodedPoint::from_affine_coordinates(x.as_slice().into(), y.as_slice().into(), false);
+    let key = EcdsaVerifyingKey::from_encoded_point(&point).ok()?;
+
+    // The signature is the concatenation of `r` and `s` rather than DER.
+    Some(match EcdsaSignature::from_slice(signature) {
+        Ok(signature) => key.verify(message, &signature).is_ok(),
+        Err(_) => false,
+    })
+}
+
+/// Returns `None` if the key itself is invalid.
+fn verify_rs256(key: &Jwk, message: &[u8], signature: &[u8]) -> Option<bool> {
+    let n = BigUint::from_bytes_be(&decode(key.n.as_deref()?).ok()?);
+    let e = BigUint::from_bytes_be(&decode(key.e.as_deref()?).ok()?);
+    let key = pkcs1v15::VerifyingKey::<Sha256>::new(RsaPublicKey::new(n, e).ok()?);
+
+    Some(match pkcs1v15::Signature::try_from(signature) {
+        Ok(signature) => key.verify(message, &signature).is_ok(),
+        Err(_) => false,
+    })
+}
+
+fn encode(bytes: &[u8]) -> String {
+    URL_SAFE_NO_PAD.encode(bytes)
+}
+
+fn decode(s: &str) -> Result<Vec<u8>, TokenError> {
+    URL_SAFE_NO_PAD.decode(s).map_err(|_| TokenError::Malformed)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use p256::ecdsa::SigningKey;
+    use rsa::{pkcs1v15::SigningKey as RsaSigningKey, traits::PublicKeyParts, RsaPrivateKey};
+    use serde_json::json;
+
+    const NOW: u64 = 1_700_000_000;
+
+    fn claims() -> serde_json::Value {
+        json!({
+            "iss": "registry-edge",
+            "sub": "robot",
+            "aud": "registry.example.com",
+            "exp": NOW + 300,
+            "iat": NOW,
+            "access": [{ "type": "repository", "name": "samalba/my-app", "actions": ["pull"] }],
+        })
+    }
+
+    fn es256_jwk(signing_key: &SigningKey) -> Jwk {
+        let point = signing_key.verifying_key().to_encoded_point(false);
+        Jwk {
+            kty: "EC".to_string(),
+            kid: Some("key-1".to_string()),
+            alg: Some("ES256".to_string()),
+            crv: Some("P-256".to_string()),
+            x: Some(encode(point.x().unwrap())),
+            y: Some(encode(point.y().unwrap())),
+            n: None,
+            e: None,
+            d: Some(encode(&signing_key.to_bytes())),
+        }
+    }
+
+    fn es256_key() -> (SigningKey, JwkSet) {
+        let signing_key = SigningKey::from_slice(&[7; 32]).unwrap();
+        let jwk = es256_jwk(&signing_key);
+        (
+            signing_key,
+            JwkSet {
+                keys: vec![jwk.public()],
+            },
+        )
+    }
+
+    fn sign_es256(signing_key: &SigningKey, kid: &str, claims: &serde_json::Value) -> String {
+        let header = json!({ "alg": "ES256", "kid": kid, "typ": "JWT" });
+        let signing_input = format!(
+            "{}.{}",
+            encode(header.to_string().as_bytes()),
+            encode(claims.to_string().as_bytes())
+        );
+        let signature: EcdsaSignature = signing_key.sign(signing_input.as_bytes());
+        format!("{}.{}", signing_input, encode(&signature.to_bytes()))
+    }
+
+    #[test]
+    fn verify_es256_token() {
+        let (signing_key, keys) = es256_key();
+        let token = sign_es256(&signing_key, "key-1", &claims());
+        let claims = verify(&token, &keys, "registry-edge", "registry.example.com", NOW).unwrap();
+        assert_eq!(claims.sub, "robot");
+        assert_eq!(claims.access[0].to_string(), "repository:samalba/my-app:pull");
+    }
+
+    #[test]
+    fn verify_rs256_token() {
+        let private_key = RsaPrivateKey::new(&mut rand::thread_rng(), 1024).unwrap();
+        let jwk = Jwk {
+            kty: "RSA".to_string(),
+            kid: None,
+            alg: Some("RS256".to_string()),
+            crv: None,
+            x: None,
+            y: None,
+            n: Some(encode(&private_key.n().to_bytes_be())),
+            e: Some(encode(&private_key.e().to_bytes_be())),
+            d: None,
+        };
+        let keys = JwkSet { keys: vec![jwk] };
+
+        let header = json!({ "alg": "RS256" });
+        let signing_input = format!(
+            "{}.{}",
+            encode(header.to_string().as_bytes()),
+            encode(claims().to_string().as_bytes())
+        );
+        let signature = RsaSigningKey::<Sha256>::new(private_key).sign(signing_input.as_bytes());
+        let token = format!("{}.{}", signing_input, encode(&Box::<[u8]>::from(signature)));
+        assert!(verify(&token, &keys, "registry-edge", "registry.example.com", NOW).is_ok());
+
+        let tampered = token.replacen('.', ".e30", 1);
+        assert!(verify(&tampered, &keys, "registry-edge", "registry.example.com", NOW).is_err());
+    }
+
+    #[test]
+    fn sign_token() {
+        let jwk = es256_jwk(&SigningKey::from_slice(&[7; 32]).unwrap());
+        let token = sign(&claims(), &jwk).unwrap();
+
+        let keys = JwkSet {
+            keys: vec![jwk.public()],
+        };
+        let (header, _) = verify_signature(&token, &keys).unwrap();
+        assert_eq!(header.kid.as_deref(), Some("key-1"));
+        assert!(verify(&token, &keys, "registry-edge", "registry.example.com", NOW).is_ok());
+        assert_eq!(sign(&claims(), &jwk.public()).err(), Some(TokenError::UnknownKey));
+    }
+
+    #[test]
+    fn reject_invalid_signature() {
+        let (_, keys) = es256_key();
+        let other_key = SigningKey::from_slice(&[9; 32]).unwrap();
+        let token = sign_es256(&other_key, "key-1", &claims());
+        assert_eq!(
+            verify_signature(&token, &keys).err(),
+            Some(TokenError::InvalidSignature)
+        );
+
+        let (signing_key, keys) = es256_key();
+        let token = sign_es256(&signing_key, "key-2", &claims());
+        assert_eq!(verify_signature(&token, &keys).err(), Some(TokenError::UnknownKey));
+    }
+
+    #[test]
+    fn reject_invalid_claims() {
+        let (signing_key, keys) = es256_key();
+        let verify = |claims: serde_json::Value| {
+            let token = sign_es256(&signing_key, "key-1", &claims);
+            verify(&token, &keys, "registry-edge", "registry.example.com", NOW).err()
+        };
+
+        let mut expired = claims();
+        expired["exp"] = json!(NOW - LEEWAY);
+        assert_eq!(verify(expired), Some(TokenError::Expired));
+
+        let mut other_issuer = claims();
+        other_issuer["iss"] = json!("someone-else");
+        assert_eq!(verify(other_issuer), Some(TokenError::InvalidClaim("iss")));
+
+        let mut other_service = claims();
+        other_service["aud"] = json!(["other.example.com"]);
+        assert_eq!(verify(other_service), Some(TokenError::InvalidClaim("aud")));
+    }
+
+    #[test]
+    fn reject_unsupported_algorithm() {
+        let header = encode(br#"{"alg":"none"}"#);
+        let payload = encode(claims().to_string().as_bytes());
+        let token = format!("{}.{}.", header, payload);
+        assert_eq!(
+            verify_signature(&token, &JwkSet::default()).err(),
+            Some(TokenError::UnsupportedAlgorithm("none".to_string()))
+        );
+        assert_eq!(
+            verify_signature("not-a-token", &JwkSet::default()).err(),
+            Some(TokenError::Malformed)
+        );
+    }
+}
@@ -0,0 +1,385 @@
+pub mod access;
+pub mod issuer;
+pub mod jwt;
+pub mod oidc;
+pub mod policy;
+
+use lazy_static::lazy_static;
+use regex::Regex;
+use worker::*;
+
+use crate::entities::repository::{RepositoryClient, RepositoryEntity};
+use crate::errors::RegistryError;
+use access::Access;
+use jwt::JwkSet;
+use std::collections::HashMap;
+
+/// Configuration of token authentication, from the variables of `wrangler.toml`.
+///
+/// See https://docs.docker.com/registry/spec/auth/token/
+pub struct AuthConfig {
+    /// The URL of the token server clients are sent to for a token.
+    pub realm: String,
+
+    /// The name of the registry, which tokens must be issued for.
+    pub service: String,
+
+    /// The issuer tokens must be issued by.
+    pub issuer: String,
+
+    /// Public keys of the issuer.
+    pub keys: JwkSet,
+
+    /// Private keys the registry signs its own tokens with. The first one is in use,
+    /// the others are kept to verify tokens signed before it has been rotated.
+    pub signing_keys: JwkSet,
+
+    /// Issuers of OIDC tokens which can be exchanged for registry tokens, e.g. by CI jobs.
+    pub oidc_providers: Vec<oidc::Provider>,
+
+    /// The rules each repository trusts OIDC tokens by, keyed by the name of the repository.
+    pub trust_rules: HashMap<String, Vec<oidc::TrustRule>>,
+}
+
+impl AuthConfig {
+    /// Read the configuration, where `url` is the URL of the request being served.
+    ///
+    /// Unless another token server is configured, clients are sent to the `/token` endpoint of the registry itself.
+    pub fn from_env(env: &Env, url: &Url) -> Result<Self> {
+        let realm = match env.var("AUTH_REALM") {
+            Ok(realm) => realm.to_string(),
+            Err(_) => format!("{}/token", url.origin().ascii_serialization()),
+        };
+        let mut keys: JwkSet = match env.var("AUTH_JWKS") {
+            Ok(keys) => serde_json::from_str(&keys.to_string())?,
+            Err(_) => JwkSet::default(),
+        };
+        let signing_keys: JwkSet = match env.secret("TOKEN_SIGNING_KEYS") {
+            Ok(signing_keys) => serde_json::from_str(&signing_keys.to_string())?,
+            Err(_) => JwkSet::default(),
+        };
+        keys.keys.extend(signing_keys.keys.iter().map(jwt::Jwk::public));
+        let oidc_providers = match env.var("OIDC_PROVIDERS") {
+            Ok(providers) => serde_json::from_str(&providers.to_string())?,
+            Err(_) => Vec::new(),
+        };
+        let trust_rules = match env.var("OIDC_TRUST_RULES") {
+            Ok(rules) => serde_json::from_str(&rules.to_string())?,
+            Err(_) => HashMap::new(),
+        };
+
+        Ok(Self {
+            realm,
+            service: env.var("AUTH_SERVICE")?.to_string(),
+            issuer: env.var("AUTH_ISSUER")?.to_string(),
+            keys,
+            signing_keys,
+            oidc_providers,
+            trust_rules,
+        })
+    }
+}
+
+/// The caller of a request, as authenticated by its token.
+#[derive(Debug, Clone, Default)]
+pub struct Caller {
+    /// The principal the token has been issued to.
+    pub subject: String,
+
+    /// The groups of the principal, from the `groups` claim of tokens of identity providers.
+    pub groups: Vec<String>,
+
+    /// The actions granted on each resource.
+    pub access: Vec<Access>,
+}
+
+impl Caller {
+    pub fn is_allowed(&self, resource_type: &str, name: &str, action: &str) -> bool {
+        self.access
+            .iter()
+            .any(|access| access.allows(resource_type, name, action))
+    }
+
+    /// Whether the token has been issued with any access to the resource at all.
+    fn is_aware_of(&self, resource_type: &str, name: &str) -> bool {
+        self.access.iter().any(|access| access.covers(resource_type, name))
+    }
+}
+
+impl From<jwt::Claims> for Caller {
+    fn from(claims: jwt::Claims) -> Self {
+        let groups = match claims.extra.get("groups") {
+            Some(serde_json::Value::Array(groups)) => groups
+                .iter()
+                .filter_map(|group| group.as_str().map(str::to_string))
+                .collect(),
+            _ => vec![],
+        };
+        Self {
+            subject: claims.sub,
+            groups,
+            access: claims.access,
+        }
+    }
+}
+
+/// What the caller of a request must be allowed.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Requirement {
+    /// Anyone, e.g. to get a token.
+    Anonymous,
+
+    /// Any caller with a valid token. The scope is only what clients are told to ask for.
+    Authenticated(Option<Access>),
+
+    /// Every action of the access.
+    Access(Access),
+}
+
+impl Requirement {
+    fn scope(&self) -> Option<&Access> {
+        match self {
+            Self::Anonymous => None,
+            Self::Authenticated(scope) => scope.as_ref(),
+            Self::Access(access) => Some(access),
+        }
+    }
+}
+
+lazy_static! {
+    static ref MANIFEST_PATH: Regex = Regex::new(r"^/v2/(.+)/manifests/[^/]+$").unwrap();
+    static ref BLOB_UPLOAD_PATH: Regex = Regex::new(r"^/v2/(.+)/blobs/uploads(/[^/]*)?$").unwrap();
+    static ref BLOB_PATH: Regex = Regex::new(r"^/v2/(.+)/blobs/[^/]+$").unwrap();
+    static ref TAGS_PATH: Regex = Regex::new(r"^/v2/(.+)/tags/list$").unwrap();
+    static ref REFERRERS_PATH: Regex = Regex::new(r"^/v2/(.+)/referrers/[^/]+$").unwrap();
+    static ref ADMIN_PATH: Regex = Regex::new(r"^/_admin/v1/repositories/([^/]+)(/.*)?$").unwrap();
+}
+
+/// Resolve what a request to the path must be allowed, following the routes of the registry API.
+pub fn requirement(method: &Method, path: &str) -> Requirement {
+    if path == "/token" {
+        return Requirement::Anonymous;
+    }
+    // Repositories are administered as a whole.
+    if let Some(captures) = ADMIN_PATH.captures(path) {
+        let name = format!("{}/*", &captures[1]);
+        return Requirement::Access(Access::new(access::REPOSITORY, &name, &[access::ADMIN]));
+    }
+    if path == "/v2/_catalog" {
+        // The catalog only lists what the caller can pull.
+        let scope = Access::new(access::REGISTRY, "catalog", &["*"]);
+        return Requirement::Authenticated(Some(scope));
+    }
+
+    let name = |regex: &Regex| regex.captures(path).map(|captures| captures[1].to_string());
+    let (name, action) = if let Some(name) = name(&MANIFEST_PATH).or_else(|| name(&BLOB_PATH)) {
+        // Uploads are matched by the blob path as well, but they are never deleted.
+        match method {
+            Method::Get | Method::Head => (name, access::PULL),
+            Method::Delete if !BLOB_UPLOAD_PATH.is_match(path) => (name, access::DELETE),
+            _ => (name, access::PUSH),
+        }
+    } else if let Some(name) = name(&BLOB_UPLOAD_PATH) {
+        (name, access::PUSH)
+    } else if let Some(name) = name(&TAGS_PATH).or_else(|| name(&REFERRERS_PATH)) {
+        (name, access::PULL)
+    } else {
+        return Requirement::Authenticated(None);
+    };
+    Requirement::Access(Access::new(access::REPOSITORY, &name, &[action]))
+}
+
+/// Why a request has been rejected.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Rejection {
+    /// The client should ask the token server for a token with the scope.
+    Unauthorized {
+        scope: Option<Access>,
+        error: Option<&'static str>,
+    },
+
+    /// The token has been issued for the resource, but without the action.
+    Denied,
+}
+
+impl Rejection {
+    pub fn to_response(&self, config: &AuthConfig) -> Result<Response> {
+        match self {
+            Self::Unauthorized { scope, error } => {
+                let mut res = RegistryError::Unauthorized.to_response()?;
+                res.headers_mut()
+                    .set("WWW-Authenticate", &challenge(config, scope.as_ref(), *error))?;
+                Ok(res)
+            }
+            Self::Denied => RegistryError::Denied.to_response(),
+        }
+    }
+}
+
+/// Authenticate the caller of the request by its bearer token, and check it is allowed what the request requires.
+///
+/// `now` is the current time in seconds since the epoch.
+pub fn authorize(
+    req: &Request,
+    requirement: &Requirement,
+    config: &AuthConfig,
+    now: u64,
+) -> std::result::Result<Caller, Rejection> {
+    if *requirement == Requirement::Anonymous {
+        return Ok(Caller::default());
+    }
+    let unauthorized = |error| Rejection::Unauthorized {
+        scope: requirement.scope().cloned(),
+        error,
+    };
+
+    let authorization = req.headers().get("Authorization").ok().flatten().unwrap_or_default();
+    let token = match authorization.strip_prefix("Bearer ") {
+        Some(token) => token.trim(),
+        None => return Err(unauthorized(None)),
+    };
+    let caller: Caller = match jwt::verify(token, &config.keys, &config.issuer, &config.service, now) {
+        Ok(claims) => claims.into(),
+        Err(_) => return Err(unauthorized(Some("invalid_token"))),
+    };
+
+    if let Requirement::Access(access) = requirement {
+        for action in &access.actions {
+            if caller.is_allowed(&access.resource_type, &access.name, action) {
+                continue;
+            }
+            // A token which doesn't mention the resource may just not have been asked for it.
+            if caller.is_aware_of(&access.resource_type, &access.name) {
+                return Err(Rejection::Denied);
+            }
+            return Err(unauthorized(Some("insufficient_scope")));
+        }
+    }
+    Ok(caller)
+}
+
+/// Authorize the request by its token, and then by the visibility and the access policy of the repository it is made to.
+///
+/// Pulling what is allowed to anyone, e.g. from a public repository, doesn't need a token at all.
+/// A token which is given must still be valid though.
+pub async fn authorize_request(
+    req: &Request,
+    requirement: &Requirement,
+    config: &AuthConfig,
+    env: &Env,
+    now: u64,
+) -> Result<std::result::Result<Caller, Rejection>> {
+    let authorized = authorize(req, requirement, config, now);
+    let access = match requirement {
+        Requirement::Access(access) if access.resource_type == access::REPOSITORY => access,
+        _ => return Ok(authorized),
+    };
+    let is_anonymous_pull = matches!(authorized, Err(Rejection::Unauthorized { error: None, .. }))
+        && access.actions.iter().all(|action| action == access::PULL);
+    if authorized.is_err() && !is_anonymous_pull {
+        return Ok(authorized);
+    }
+
+    let repository_name = access.name.split('/').next().unwrap_or_default();
+    let control = RepositoryClient::new(env, repository_name)?.access_control().await?;
+    Ok(match authorized {
+        Ok(caller) => {
+            let allowed = access
+                .actions
+                .iter()
+                .all(|action| control.allows(&caller, &access.name, action));
+            if allowed {
+                Ok(caller)
+            } else {
+                Err(Rejection::Denied)
+            }
+        }
+        Err(rejection) => {
+            let allowed = access
+                .actions
+                .iter()
+                .all(|action| control.allows_anyone(&access.name, action));
+            if allowed {
+                Ok(Caller::default())
+            } else {
+                Err(rejection)
+            }
+        }
+    })
+}
+
+/// The `WWW-Authenticate` challenge which tells the client where to get a token for the scope.
+fn challenge(config: &AuthConfig, scope: Option<&Access>, error: Option<&str>) -> String {
+    let mut challenge = format!("Bearer realm=\"{}\",service=\"{}\"", config.realm, config.service);
+    if let Some(scope) = scope {
+        challenge.push_str(&format!(",scope=\"{}\"", scope));
+    }
+    if let Some(error) = error {
+        challenge.push_str(&format!(",error=\"{}\"", error));
+    }
+    challenge
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn access(name: &str, action: &str) -> Requirement {
+        Requirement::Access(Access::new(access::REPOSITORY, name, &[action]))
+    }
+
+    #[test]
+    fn resolve_requirement() {
+        assert_eq!(requirement(&Method::Get, "/token"), Requirement::Anonymous);
+        assert_eq!(requirement(&Method::Get, "/v2/"), Requirement::Authenticated(None));
+        assert_eq!(
+            requirement(&Method::Get, "/v2/_catalog"),
+            Requirement::Authenticated(Some(Access::new(access::REGISTRY, "catalog", &["*"])))
+        );
+
+        assert_eq!(
+            requirement(&Method::Post, "/_admin/v1/repositories/samalba/robots"),
+            Requirement::Access(Access::new(access::REPOSITORY, "samalba/*", &[access::ADMIN]))
+        );
+
+        let cases = [
+            (Method::Get, "/v2/samalba/my-app/manifests/latest", access::PULL),
+            (Method::Head, "/v2/samalba/my-app/manifests/latest", access::PULL),
+            (Method::Put, "/v2/samalba/my-app/manifests/latest", access::PUSH),
+            (Method::Delete, "/v2/samalba/my-app/manifests/latest", access::DELETE),
+            (Method::Get, "/v2/samalba/my-app/blobs/sha256:abc", access::PULL),
+            (Method::Delete, "/v2/samalba/my-app/blobs/sha256:abc", access::DELETE),
+            (Method::Post, "/v2/samalba/my-app/blobs/uploads/", access::PUSH),
+            (Method::Patch, "/v2/samalba/my-app/blobs/uploads/uuid", access::PUSH),
+            (Method::Delete, "/v2/samalba/my-app/blobs/uploads/uuid", access::PUSH),
+            (Method::Get, "/v2/samalba/my-app/tags/list", access::PULL),
+            (Method::Get, "/v2/samalba/my-app/referrers/sha256:abc", access::PULL),
+        ];
+        for (method, path, action) in cases {
+            assert_eq!(requirement(&method, path), access("samalba/my-app", action), "{}", path);
+        }
+    }
+
+    #[test]
+    fn challenge_for_scope() {
+        let config = AuthConfig {
+            realm: "https://auth.example.com/token".to_string(),
+            service: "registry.example.com".to_string(),
+            issuer: "registry-edge".to_string(),
+            keys: JwkSet::default(),
+            signing_keys: JwkSet::default(),
+            oidc_providers: Vec::new(),
+            trust_rules: HashMap::new(),
+        };
+        assert_eq!(
+            challenge(&config, None, None),
+            r#"Bearer realm="https://auth.example.com/token",service="registry.example.com""#
+        );
+
+        let scope = Access::new(access::REPOSITORY, "samalba/my-app", &[access::PULL, access::PUSH]);
+        assert_eq!(
+            challenge(&config, Some(&scope), Some("insufficient_scope")),
+            r#"Bearer realm="https://auth.example.com/token",service="registry.example.com",scope="repository:samalba/my-app:pull,push",error="insufficient_scope""#
+        );
+    }
+}
@@ -0,0 +1,242 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+use super::access::{self, Access};
+use super::jwt::{self, Claims, JwkSet, TokenError};
+use crate::utils::glob_match;
+
+/// An OIDC issuer whose ID tokens are exchanged for registry tokens, e.g. GitHub Actions.
+///
+/// Its keys are configured rather than discovered, so tokens are verified without fetching anything.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Provider {
+    /// The `iss` claim of its tokens, e.g. `https://token.actions.githubusercontent.com`.
+    pub issuer: String,
+
+    /// The `aud` its tokens must be issued for, the service of the registry by default.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub audience: Option<String>,
+
+    pub jwks: JwkSet,
+}
+
+/// Which CI jobs a repository trusts, and with what.
+///
+/// For example, pushing `samalba/my-app` from the `main` branch of a GitHub repository:
+///
+/// ```json
+/// {
+///   "issuer": "https://token.actions.githubusercontent.com",
+///   "claims": { "repository": "samalba/my-app", "ref": "refs/heads/main" },
+///   "images": ["samalba/my-app", "samalba/my-app/*"],
+///   "actions": ["pull", "push"]
+/// }
+/// ```
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TrustRule {
+    pub issuer: String,
+
+    /// Patterns every claim of the token must match, where `*` matches anything.
+    #[serde(default)]
+    pub claims: HashMap<String, String>,
+
+    /// Patterns of the `$repository/$image` names the token gets access to. Every image of the repository if empty.
+    #[serde(default)]
+    pub images: Vec<String>,
+
+    #[serde(default = "default_actions")]
+    pub actions: Vec<String>,
+}
+
+fn default_actions() -> Vec<String> {
+    vec![access::PULL.to_string(), access::PUSH.to_string()]
+}
+
+impl TrustRule {
+    /// Whether the rule trusts the verified ID token.
+    pub fn trusts(&self, claims: &Claims) -> bool {
+        self.issuer == claims.iss
+            && self.claims.iter().all(|(name, pattern)| {
+                let value = match name.as_str() {
+                    "sub" => Some(claims.sub.as_str()),
+                    _ => claims.extra.get(name).and_then(|value| value.as_str()),
+                };
+                value.map_or(false, |value| glob_match(pattern, value))
+            })
+    }
+
+    fn covers(&self, name: &str) -> bool {
+        self.images.is_empty() || self.images.iter().any(|pattern| glob_match(pattern, name))
+    }
+}
+
+/// Verify an ID token against the provider which issued it.
+pub fn verify(token: &str, providers: &[Provider], service: &str, now: u64) -> Result<Claims, TokenError> {
+    let mut result = Err(TokenError::UnknownKey);
+    for provider in providers {
+        let audience = provider.audience.as_deref().unwrap_or(service);
+        result = jwt::verify(token, &provider.jwks, &provider.issuer, audience, now);
+        if result.is_ok() {
+            break;
+        }
+    }
+    result
+}
+
+/// Narrow the requested scopes down to what the rules of the repository trust the ID token with.
+///
+/// Only images of the repository are granted, and never the catalog.
+pub fn grant(repository_name: &str, rules: &[TrustRule], claims: &Claims, requested: &[Access]) -> Vec<Access> {
+    let tenant = Access::new(access::REPOSITORY, &format!("{}/*", repository_name), &[]);
+    let rules: Vec<&TrustRule> = rules.iter().filter(|rule| rule.trusts(claims)).collect();
+
+    let mut granted: Vec<Access> = Vec::new();
+    for scope in requested {
+        if !tenant.covers(&scope.resource_type, &scope.name) {
+            continue;
+        }
+        let actions: Vec<&str> = scope
+            .actions
+            .iter()
+            .map(String::as_str)
+            .filter(|action| {
+                rules.iter().any(|rule| {
+                    rule.covers(&scope.name) && rule.actions.iter().any(|granted| granted == action || granted == "*")
+                })
+            })
+            .collect();
+        if !actions.is_empty() {
+            granted.push(Access::new(access::REPOSITORY, &scope.name, &actions));
+        }
+    }
+    granted
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+    use jwt::{Audience, Jwk};
+    use p256::ecdsa::SigningKey;
+    use serde_json::json;
+
+    const GITHUB: &str = "https://token.actions.githubusercontent.com";
+
+    fn claims(repository: &str, git_ref: &str) -> Claims {
+        Claims {
+            iss: GITHUB.to_string(),
+            sub: format!("repo:{}:ref:{}", repository, git_ref),
+            aud: Audience::One("registry-edge".to_string()),
+            exp: 1_700_000_300,
+            nbf: None,
+            iat: Some(1_700_000_000),
+            jti: None,
+            access: vec![],
+            extra: HashMap::from([
+                ("repository".to_string(), json!(repository)),
+                ("ref".to_string(), json!(git_ref)),
+            ]),
+        }
+    }
+
+    fn rule() -> TrustRule {
+        serde_json::from_value(json!({
+            "issuer": GITHUB,
+            "claims": { "repository": "samalba/my-app", "ref": "refs/heads/*" },
+            "images": ["samalba/my-app", "samalba/my-app/*"],
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn trust_matching_claims() {
+        let rule = rule();
+        assert_eq!(rule.actions, vec![access::PULL, access::PUSH]);
+        assert!(rule.trusts(&claims("samalba/my-app", "refs/heads/main")));
+        assert!(!rule.trusts(&claims("samalba/my-app", "refs/tags/v1.0.0")));
+        assert!(!rule.trusts(&claims("samalba/other-app", "refs/heads/main")));
+
+        let mut other_issuer = claims("samalba/my-app", "refs/heads/main");
+        other_issuer.iss = "https://gitlab.example.com".to_string();
+        assert!(!rule.trusts(&other_issuer));
+    }
+
+    #[test]
+    fn grant_trusted_images() {
+        let rules = vec![rule()];
+        let requested = vec![
+            Access::new(
+                access::REPOSITORY,
+                "samalba/my-app",
+                &[access::PULL, access::PUSH, access::DELETE],
+            ),
+            Access::new(access::REPOSITORY, "samalba/my-app/cache", &[access::PUSH]),
+            Access::new(access::REPOSITORY, "samalba/other-app", &[access::PUSH]),
+            Access::new(access::REGISTRY, "catalog", &["*"]),
+        ];
+        assert_eq!(
+            grant(
+                "samalba",
+                &rules,
+                &claims("samalba/my-app", "refs/heads/main"),
+                &requested
+            ),
+            vec![
+                Access::new(access::REPOSITORY, "samalba/my-app", &[access::PULL, access::PUSH]),
+                Access::new(access::REPOSITORY, "samalba/my-app/cache", &[access::PUSH]),
+            ]
+        );
+        assert!(grant(
+            "samalba",
+            &rules,
+            &claims("samalba/my-app", "refs/pull/1/merge"),
+            &requested
+        )
+        .is_empty());
+        assert!(grant(
+            "other",
+            &rules,
+            &claims("samalba/my-app", "refs/heads/main"),
+            &requested
+        )
+        .is_empty());
+    }
+
+    #[test]
+    fn verify_against_configured_issuer() {
+        let key = SigningKey::from_slice(&[5; 32]).unwrap();
+        let point = key.verifying_key().to_encoded_point(false);
+        let encode = |bytes: &[u8]| URL_SAFE_NO_PAD.encode(bytes);
+        let jwk = Jwk {
+            kty: "EC".to_string(),
+            kid: Some("github".to_string()),
+            alg: Some("ES256".to_string()),
+            crv: Some("P-256".to_string()),
+            x: Some(encode(point.x().unwrap())),
+            y: Some(encode(point.y().unwrap())),
+            n: None,
+            e: None,
+            d: Some(encode(&key.to_bytes())),
+        };
+        let providers = vec![Provider {
+            issuer: GITHUB.to_string(),
+            audience: None,
+            jwks: JwkSet {
+                keys: vec![jwk.public()],
+            },
+        }];
+
+        let token = jwt::sign(&claims("samalba/my-app", "refs/heads/main"), &jwk).unwrap();
+        let verified = verify(&token, &providers, "registry-edge", 1_700_000_000).unwrap();
+        assert_eq!(verified.extra["repository"], json!("samalba/my-app"));
+
+        assert_eq!(
+            verify(&token, &providers, "other-registry", 1_700_000_000).unwrap_err(),
+            TokenError::InvalidClaim("aud")
+        );
+        assert_eq!(
+            verify(&token, &[], "registry-edge", 1_700_000_000).unwrap_err(),
+            TokenError::UnknownKey
+        );
+    }
+}
@@ -0,0 +1,197 @@
+use serde::{Deserialize, Serialize};
+
+use super::{access, Caller};
+use crate::utils::glob_match;
+
+/// Who can do what on the images of a repository.
+///
+/// Once a repository has a policy, a caller must be allowed an action by both its token and the policy.
+/// For example, letting anyone pull the images, and the CI robot push them:
+///
+/// ```json
+/// {
+///   "statements": [
+///     { "principals": ["*"], "actions": ["pull"] },
+///     { "principals": ["samalba+ci"], "groups": ["platform"], "actions": ["push"], "images": ["samalba/base/*"] }
+///   ]
+/// }
+/// ```
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Policy {
+    pub statements: Vec<Statement>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Statement {
+    /// Patterns of the subjects of tokens, where `*` matches anything. `*` alone stands for anyone,
+    /// even callers without credentials.
+    #[serde(default)]
+    pub principals: Vec<String>,
+
+    /// Groups of the `groups` claim of tokens.
+    #[serde(default)]
+    pub groups: Vec<String>,
+
+    /// `pull`, `push`, `delete`, `admin`, or `*` for any of them.
+    pub actions: Vec<String>,
+
+    /// Patterns of the `$repository/$image` names the statement is narrowed to. Every image of the repository if empty,
+    /// which is also the only way to grant `admin`.
+    #[serde(default)]
+    pub images: Vec<String>,
+}
+
+impl Statement {
+    fn applies_to(&self, caller: &Caller) -> bool {
+        self.principals
+            .iter()
+            .any(|principal| glob_match(principal, &caller.subject))
+            || self.groups.iter().any(|group| caller.groups.contains(group))
+    }
+
+    fn covers(&self, name: &str) -> bool {
+        self.images.is_empty() || self.images.iter().any(|pattern| glob_match(pattern, name))
+    }
+}
+
+impl Policy {
+    /// Whether the policy allows the caller the action on the resource, a `$repository/$image` name or `$repository/*`.
+    pub fn allows(&self, caller: &Caller, name: &str, action: &str) -> bool {
+        self.statements.iter().any(|statement| {
+            statement.applies_to(caller)
+                && statement.covers(name)
+                && statement
+                    .actions
+                    .iter()
+                    .any(|granted| granted == action || granted == "*")
+        })
+    }
+
+    /// Whether the policy has a say on the action.
+    ///
+    /// `admin` is left to tokens unless granted to someone, so that a policy can't lock administrators out.
+    pub fn governs(&self, action: &str) -> bool {
+        action != access::ADMIN
+            || self.statements.iter().any(|statement| {
+                statement
+                    .actions
+                    .iter()
+                    .any(|granted| granted == action || granted == "*")
+            })
+    }
+
+    /// Whether the action is allowed to anyone, even without credentials.
+    pub fn allows_anyone(&self, name: &str, action: &str) -> bool {
+        self.allows(&Caller::default(), name, action)
+    }
+}
+
+/// Whether anyone can pull the images of a repository, even without credentials.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Visibility {
+    #[default]
+    Private,
+    Public,
+}
+
+/// What decides access to the images of a repository, on top of tokens.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct AccessControl {
+    #[serde(default)]
+    pub visibility: Visibility,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub policy: Option<Policy>,
+}
+
+impl AccessControl {
+    /// Whether the caller, whose token allows the action, can do it on the resource.
+    pub fn allows(&self, caller: &Caller, name: &str, action: &str) -> bool {
+        (self.visibility == Visibility::Public && action == access::PULL)
+            || self.policy.as_ref().map_or(true, |policy| {
+                !policy.governs(action) || policy.allows(caller, name, action)
+            })
+    }
+
+    /// Whether the action on the resource is allowed to anyone, even without credentials.
+    pub fn allows_anyone(&self, name: &str, action: &str) -> bool {
+        (self.visibility == Visibility::Public && action == access::PULL)
+            || self
+                .policy
+                .as_ref()
+                .map_or(false, |policy| policy.allows_anyone(name, action))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json::json;
+
+    fn policy() -> Policy {
+        serde_json::from_value(json!({
+            "statements": [
+                { "principals": ["*"], "actions": ["pull"], "images": ["samalba/base/*"] },
+                { "principals": ["samalba+ci", "repo:samalba/*"], "actions": ["pull", "push"] },
+                { "groups": ["platform"], "actions": ["*"] },
+            ]
+        }))
+        .unwrap()
+    }
+
+    fn caller(subject: &str, groups: &[&str]) -> Caller {
+        Caller {
+            subject: subject.to_string(),
+            groups: groups.iter().map(|group| group.to_string()).collect(),
+            access: vec![],
+        }
+    }
+
+    #[test]
+    fn allow_principals() {
+        let mut policy = policy();
+        let ci = caller("samalba+ci", &[]);
+        assert!(policy.allows(&ci, "samalba/my-app", access::PUSH));
+        assert!(!policy.allows(&ci, "samalba/my-app", access::DELETE));
+        assert!(policy.allows(
+            &caller("repo:samalba/my-app:ref:refs/heads/main", &[]),
+            "samalba/my-app",
+            access::PUSH
+        ));
+
+        let alice = caller("alice", &["platform"]);
+        assert!(policy.allows(&alice, "samalba/my-app", access::DELETE));
+        assert!(policy.allows(&alice, "samalba/*", access::ADMIN));
+        assert!(!policy.allows(&caller("bob", &["dev"]), "samalba/my-app", access::PULL));
+
+        assert!(policy.governs(access::ADMIN));
+        policy.statements.pop();
+        assert!(!policy.governs(access::ADMIN));
+        assert!(policy.governs(access::DELETE));
+    }
+
+    #[test]
+    fn allow_anyone_to_pull_public_images() {
+        let policy = policy();
+        assert!(policy.allows_anyone("samalba/base/alpine", access::PULL));
+        assert!(!policy.allows_anyone("samalba/base/alpine", access::PUSH));
+        assert!(!policy.allows_anyone("samalba/my-app", access::PULL));
+        assert!(policy.allows(&caller("bob", &[]), "samalba/base/alpine", access::PULL));
+    }
+
+    #[test]
+    fn allow_anyone_to_pull_public_repository() {
+        let mut control = AccessControl::default();
+        assert!(!control.allows_anyone("samalba/my-app", access::PULL));
+        assert!(control.allows(&caller("bob", &[]), "samalba/my-app", access::PUSH));
+
+        control.visibility = Visibility::Public;
+        control.policy = Some(policy());
+        assert!(control.allows_anyone("samalba/my-app", access::PULL));
+        assert!(!control.allows_anyone("samalba/my-app", access::PUSH));
+        assert!(control.allows(&caller("bob", &[]), "samalba/my-app", access::PULL));
+        assert!(!control.allows(&caller("bob", &[]), "samalba/my-app", access::PUSH));
+        assert!(control.allows(&caller("samalba+ci", &[]), "samalba/my-app", access::PUSH));
+    }
+}
@@ -0,0 +1,118 @@
+use worker::{Bucket, Env, Result};
+
+use crate::entities::catalog::{CatalogClient, CatalogEntity};
+use crate::entities::repository::{RepositoryClient, RepositoryEntity};
+use crate::entities::upload_session::{UploadSessionClient, UploadSessionEntity};
+use crate::storage::{self, BlobStorage};
+
+/// Where everything the registry serves is kept.
+///
+/// Controllers are written against it rather than against Cloudflare bindings, so that they can be served
+/// from memory in tests as well. See the storages of `DESIGN.md`.
+pub trait Backend {
+    type Blobs: BlobStorage;
+    type Catalog: CatalogEntity;
+    type UploadSession: UploadSessionEntity;
+    type Repository: RepositoryEntity;
+
+    fn blobs(&self) -> Result<Self::Blobs>;
+
+    fn catalog(&self) -> Result<Self::Catalog>;
+
+    fn upload_session(&self, uuid: &str) -> Result<Self::UploadSession>;
+
+    fn repository(&self, repository_name: &str) -> Result<Self::Repository>;
+}
+
+/// The content streamed from the blob storage of a backend.
+pub type BlobStream<S> = <<S as Backend>::Blobs as BlobStorage>::Stream;
+
+/// The bindings of the worker, i.e. R2 and Durable Objects.
+pub struct WorkerBackend {
+    env: Env,
+}
+
+impl WorkerBackend {
+    pub fn new(env: Env) -> Self {
+        Self { env }
+    }
+}
+
+impl Backend for WorkerBackend {
+    type Blobs = Bucket;
+    type Catalog = CatalogClient;
+    type UploadSession = UploadSessionClient;
+    type Repository = RepositoryClient;
+
+    fn blobs(&self) -> Result<Bucket> {
+        self.env.bucket(storage::BUCKET_BINDING)
+    }
+
+    fn catalog(&self) -> Result<CatalogClient> {
+        CatalogClient::new(&self.env)
+    }
+
+    fn upload_session(&self, uuid: &str) -> Result<UploadSessionClient> {
+        UploadSessionClient::new(&self.env, uuid)
+    }
+
+    fn repository(&self, repository_name: &str) -> Result<RepositoryClient> {
+        RepositoryClient::new(&self.env, repository_name)
+    }
+}
+
+#[cfg(test)]
+pub mod memory {
+    use std::cell::RefCell;
+    use std::collections::HashMap;
+    use std::rc::Rc;
+    use worker::Result;
+
+    use super::Backend;
+    use crate::entities::catalog::{self, CatalogState};
+    use crate::entities::repository::{self, RepositoryState};
+    use crate::entities::upload_session::{self, UploadSessionState};
+    use crate::storage::memory::{MemoryBlobStorage, MemoryEntityStorage};
+
+    /// Keeps everything in memory, running entities in place of their Durable Objects.
+    /// Clones share the same storages.
+    #[derive(Debug, Clone, Default)]
+    pub struct MemoryBackend {
+        pub blobs: MemoryBlobStorage,
+        entities: Rc<RefCell<HashMap<(&'static str, String), MemoryEntityStorage>>>,
+    }
+
+    impl MemoryBackend {
+        /// The storage of the entity of the binding with the name, as `id_from_name` would find it.
+        pub fn entity(&self, binding: &'static str, name: &str) -> MemoryEntityStorage {
+            self.entities
+                .borrow_mut()
+                .entry((binding, name.to_string()))
+                .or_default()
+                .clone()
+        }
+    }
+
+    impl Backend for MemoryBackend {
+        type Blobs = MemoryBlobStorage;
+        type Catalog = CatalogState<MemoryEntityStorage>;
+        type UploadSession = UploadSessionState<MemoryEntityStorage>;
+        type Repository = RepositoryState<MemoryEntityStorage>;
+
+        fn blobs(&self) -> Result<MemoryBlobStorage> {
+            Ok(self.blobs.clone())
+        }
+
+        fn catalog(&self) -> Result<Self::Catalog> {
+            Ok(CatalogState::new(self.entity(catalog::BINDING, catalog::CATALOG_NAME)))
+        }
+
+        fn upload_session(&self, uuid: &str) -> Result<Self::UploadSession> {
+            Ok(UploadSessionState::new(self.entity(upload_session::BINDING, uuid)))
+        }
+
+        fn repository(&self, repository_name: &str) -> Result<Self::Repository> {
+            Ok(RepositoryState::new(self.entity(repository::BINDING, repository_name)))
+        }
+    }
+}
@@ -0,0 +1,318 @@
+use lazy_static::lazy_static;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use worker::*;
+
+use crate::auth::policy::{Policy, Visibility};
+use crate::auth::{access, Caller};
+use crate::entities::repository::{Credential, PersonalAccessToken, RepositoryClient, RepositoryEntity, Robot, Secret};
+use crate::errors::{ErrorBody, ErrorInfo};
+
+lazy_static! {
+    static ref ROBOT_NAME: Regex = Regex::new(r"^[a-z0-9]+(?:[._-][a-z0-9]+)*$").unwrap();
+}
+
+/// The actions robots and personal access tokens can be allowed.
+const ACTIONS: [&str; 5] = [access::PULL, access::PUSH, access::DELETE, access::ADMIN, "*"];
+
+#[derive(Debug, Deserialize)]
+struct CreateRobot {
+    name: String,
+    actions: Vec<String>,
+    expires_at: Option<u64>,
+}
+
+#[derive(Debug, Deserialize)]
+struct CreateToken {
+    owner: String,
+    description: Option<String>,
+    actions: Vec<String>,
+    expires_at: Option<u64>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct Rotate {
+    /// The expiry of the new secret, the one of the previous secret if not given.
+    expires_at: Option<u64>,
+}
+
+/// List the robots or personal access tokens of the repository.
+///
+/// Secrets are never listed, not even hashed.
+pub async fn list<T: Credential>(_req: Request, ctx: RouteContext<Caller>) -> Result<Response> {
+    let repository_name = ctx.param("repository_name").unwrap();
+    let credentials = RepositoryClient::new(&ctx.env, repository_name)?.list::<T>().await?;
+    let described = credentials
+        .iter()
+        .map(|credential| describe(credential, None))
+        .collect::<Result<Vec<_>>>()?;
+    Response::from_json(&described)
+}
+
+pub async fn get<T: Credential>(_req: Request, ctx: RouteContext<Caller>) -> Result<Response> {
+    let repository_name = ctx.param("repository_name").unwrap();
+    let id = ctx.param("id").unwrap();
+    match RepositoryClient::new(&ctx.env, repository_name)?.get::<T>(id).await? {
+        Some(credential) => Response::from_json(&describe(&credential, None)?),
+        None => not_found::<T>(id),
+    }
+}
+
+/// Create a robot, whose secret is returned once and for all.
+pub async fn create_robot(mut req: Request, ctx: RouteContext<Caller>) -> Result<Response> {
+    let repository_name = ctx.param("repository_name").unwrap();
+    let body: CreateRobot = match req.json().await {
+        Ok(body) => body,
+        Err(err) => return error(400, "INVALID_REQUEST", &err.to_string()),
+    };
+    if !ROBOT_NAME.is_match(&body.name) {
+        return error(400, "INVALID_REQUEST", "invalid robot name");
+    }
+    let now = Date::now().as_millis() / 1000;
+    if let Err(message) = validate(&body.actions, body.expires_at, now) {
+        return error(400, "INVALID_REQUEST", message);
+    }
+
+    let (secret, stored) = Secret::generate(now, body.expires_at)?;
+    let robot = Robot {
+        name: body.name,
+        secret: stored,
+        actions: body.actions,
+        created_at: now,
+    };
+    create(&ctx, repository_name, &robot, &secret).await
+}
+
+/// Create a personal access token, whose secret is returned once and for all.
+pub async fn create_token(mut req: Request, ctx: RouteContext<Caller>) -> Result<Response> {
+    let repository_name = ctx.param("repository_name").unwrap();
+    let body: CreateToken = match req.json().await {
+        Ok(body) => body,
+        Err(err) => return error(400, "INVALID_REQUEST", &err.to_string()),
+    };
+    // The owner is the name the token logs in with, which can't hold the separators of Basic credentials.
+    if body.owner.is_empty() || body.owner.contains([':', '+']) {
+        return error(400, "INVALID_REQUEST", "invalid owner");
+    }
+    let now = Date::now().as_millis() / 1000;
+    if let Err(message) = validate(&body.actions, body.expires_at, now) {
+        return error(400, "INVALID_REQUEST", message);
+    }
+
+    let (secret, stored) = Secret::generate(now, body.expires_at)?;
+    let token = PersonalAccessToken {
+        id: uuid::Uuid::new_v4().to_string(),
+        owner: body.owner,
+        description: body.description,
+        secret: stored,
+        actions: body.actions,
+        created_at: now,
+    };
+    create(&ctx, repository_name, &token, &secret).await
+}
+
+/// Replace the secret of a robot or a personal access token, which revokes the previous one.
+///
+/// The new secret is returned once and for all.
+pub async fn rotate<T: Credential>(mut req: Request, ctx: RouteContext<Caller>) -> Result<Response> {
+    let repository_name = ctx.param("repository_name").unwrap();
+    let id = ctx.param("id").unwrap();
+    // The body is optional.
+    let body = match req.text().await? {
+        text if text.trim().is_empty() => Rotate::default(),
+        text => match serde_json::from_str::<Rotate>(&text) {
+            Ok(body) => body,
+            Err(err) => return error(400, "INVALID_REQUEST", &err.to_string()),
+        },
+    };
+
+    let mut client = RepositoryClient::new(&ctx.env, repository_name)?;
+    let current = match client.get::<T>(id).await? {
+        Some(current) => current,
+        None => return not_found::<T>(id),
+    };
+    let now = Date::now().as_millis() / 1000;
+    let expires_at = body.expires_at.or(current.secret().expires_at);
+    if expires_at.map_or(false, |expires_at| expires_at <= now) {
+        return error(400, "INVALID_REQUEST", "expiry is in the past");
+    }
+
+    let (secret, stored) = Secret::generate(now, expires_at)?;
+    match client.rotate::<T>(id, &stored).await? {
+        Some(credential) => Response::from_json(&describe(&credential, Some(&secret))?),
+        None => not_found::<T>(id),
+    }
+}
+
+/// Delete a robot or a personal access token, which can no longer log in.
+///
+/// Access tokens already issued remain valid until they expire, which doesn't take long.
+pub async fn revoke<T: Credential>(_req: Request, ctx: RouteContext<Caller>) -> Result<Response> {
+    let repository_name = ctx.param("repository_name").unwrap();
+    let id = ctx.param("id").unwrap();
+    match RepositoryClient::new(&ctx.env, repository_name)?
+        .delete::<T>(id)
+        .await?
+    {
+        Some(_) => Ok(Response::empty()?.with_status(204)),
+        None => not_found::<T>(id),
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct VisibilitySetting {
+    visibility: Visibility,
+}
+
+/// Get whether the images of the repository can be pulled without credentials.
+pub async fn get_visibility(_req: Request, ctx: RouteContext<Caller>) -> Result<Response> {
+    let repository_name = ctx.param("repository_name").unwrap();
+    let visibility = RepositoryClient::new(&ctx.env, repository_name)?.visibility().await?;
+    Response::from_json(&VisibilitySetting { visibility })
+}
+
+/// Make the images of the repository `public`, which anyone can pull without credentials, or `private`.
+///
+/// Pushing, deleting and listing the catalog still need credentials either way.
+pub async fn put_visibility(mut req: Request, ctx: RouteContext<Caller>) -> Result<Response> {
+    let repository_name = ctx.param("repository_name").unwrap();
+    let setting: VisibilitySetting = match req.json().await {
+        Ok(setting) => setting,
+        Err(err) => return error(400, "INVALID_REQUEST", &err.to_string()),
+    };
+    RepositoryClient::new(&ctx.env, repository_name)?
+        .set_visibility(setting.visibility)
+        .await?;
+    Response::from_json(&setting)
+}
+
+/// Get the access policy of the repository.
+pub async fn get_policy(_req: Request, ctx: RouteContext<Caller>) -> Result<Response> {
+    let repository_name = ctx.param("repository_name").unwrap();
+    match RepositoryClient::new(&ctx.env, repository_name)?.policy().await? {
+        Some(policy) => Response::from_json(&policy),
+        None => error(404, "NOT_FOUND", "the repository has no policy"),
+    }
+}
+
+/// Attach an access policy to the repository, replacing the previous one.
+pub async fn put_policy(mut req: Request, ctx: RouteContext<Caller>) -> Result<Response> {
+    let repository_name = ctx.param("repository_name").unwrap();
+    let policy: Policy = match req.json().await {
+        Ok(policy) => policy,
+        Err(err) => return error(400, "INVALID_REQUEST", &err.to_string()),
+    };
+    for statement in &policy.statements {
+        if let Err(message) = validate(&statement.actions, None, 0) {
+            return error(400, "INVALID_REQUEST", message);
+        }
+    }
+    RepositoryClient::new(&ctx.env, repository_name)?
+        .put_policy(&policy)
+        .await?;
+    Response::from_json(&policy)
+}
+
+/// Detach the access policy of the repository, leaving access to tokens alone.
+pub async fn delete_policy(_req: Request, ctx: RouteContext<Caller>) -> Result<Response> {
+    let repository_name = ctx.param("repository_name").unwrap();
+    match RepositoryClient::new(&ctx.env, repository_name)?
+        .delete_policy()
+        .await?
+    {
+        true => Ok(Response::empty()?.with_status(204)),
+        false => error(404, "NOT_FOUND", "the repository has no policy"),
+    }
+}
+
+async fn create<T: Credential>(
+    ctx: &RouteContext<Caller>,
+    repository_name: &str,
+    credential: &T,
+    secret: &str,
+) -> Result<Response> {
+    if !RepositoryClient::new(&ctx.env, repository_name)?
+        .create(credential)
+        .await?
+    {
+        return error(409, "CONFLICT", &format!("{} already exists", credential.id()));
+    }
+    Ok(Response::from_json(&describe(credential, Some(secret))?)?.with_status(201))
+}
+
+fn validate(actions: &[String], expires_at: Option<u64>, now: u64) -> std::result::Result<(), &'static str> {
+    if actions.is_empty() || actions.iter().any(|action| !ACTIONS.contains(&action.as_str())) {
+        return Err("invalid actions");
+    }
+    if expires_at.map_or(false, |expires_at| expires_at <= now) {
+        return Err("expiry is in the past");
+    }
+    Ok(())
+}
+
+/// Describe the credential without the hash of its secret, along with the password it logs in with if just issued.
+fn describe<T: Credential>(credential: &T, secret: Option<&str>) -> Result<Value> {
+    let mut value = serde_json::to_value(credential)?;
+    if let Some(object) = value.as_object_mut() {
+        object.remove("secret_hash");
+        if let Some(secret) = secret {
+            object.insert("secret".to_string(), Value::String(credential.password(secret)));
+        }
+    }
+    Ok(value)
+}
+
+fn not_found<T: Credential>(id: &str) -> Result<Response> {
+    error(404, "NOT_FOUND", &format!("{} not found: {}", T::KIND, id))
+}
+
+/// An error in the same shape as the errors of the registry API.
+fn error(status: u16, code: &'static str, message: &str) -> Result<Response> {
+    let body = ErrorBody {
+        errors: vec![ErrorInfo {
+            code,
+            message: message.to_string(),
+            detail: None,
+        }],
+    };
+    Ok(Response::from_json(&body)?.with_status(status))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn validate_credential() {
+        let actions = vec![access::PULL.to_string(), access::PUSH.to_string()];
+        assert!(validate(&actions, None, 1_700_000_000).is_ok());
+        assert!(validate(&actions, Some(1_800_000_000), 1_700_000_000).is_ok());
+        assert!(validate(&actions, Some(1_600_000_000), 1_700_000_000).is_err());
+        assert!(validate(&[], None, 1_700_000_000).is_err());
+        assert!(validate(&["write".to_string()], None, 1_700_000_000).is_err());
+
+        assert!(ROBOT_NAME.is_match("ci-bot"));
+        assert!(!ROBOT_NAME.is_match("ci+bot"));
+    }
+
+    #[test]
+    fn describe_without_hash() {
+        let (secret, stored) = Secret::generate(1_700_000_000, None).unwrap();
+        let token = PersonalAccessToken {
+            id: "d3b07384".to_string(),
+            owner: "alice".to_string(),
+            description: None,
+            secret: stored,
+            actions: vec![access::PULL.to_string()],
+            created_at: 1_700_000_000,
+        };
+        let value = describe(&token, None).unwrap();
+        assert!(value.get("secret_hash").is_none());
+        assert!(value.get("secret").is_none());
+        assert_eq!(value["secret_issued_at"], 1_700_000_000);
+
+        let value = describe(&token, Some(&secret)).unwrap();
+        assert_eq!(value["secret"], format!("pat_d3b07384_{}", secret));
+    }
+}
@@ -1 +1,3 @@
+pub mod admin;
+pub mod token;
 pub mod v2;
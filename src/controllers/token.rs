@@ -0,0 +1,252 @@
+use base64::{engine::general_purpose::STANDARD, Engine};
+use worker::*;
+
+use crate::auth::access::{self, Access};
+use crate::auth::issuer::{self, TokenResponse};
+use crate::auth::oidc;
+use crate::auth::{AuthConfig, Caller};
+use crate::entities::repository::{
+    parse_token, parse_username, PersonalAccessToken, RepositoryClient, RepositoryEntity, Robot,
+};
+use crate::errors::RegistryError;
+
+/// How a client proves who it is to the token endpoint.
+enum Credentials {
+    Anonymous,
+    Password { username: String, password: String },
+    RefreshToken(String),
+}
+
+/// Get a token for the scopes, with the credentials of a robot or the ID token of a CI job given by Basic authentication.
+///
+/// Several scopes may be requested by repeating the `scope` parameter. With `offline_token=true`, e.g. on
+/// `docker login`, a refresh token is issued along, which gets access tokens later on without the credentials.
+/// Without credentials, the token only grants what the repositories allow anyone, e.g. pulling public images.
+///
+/// See https://docs.docker.com/registry/spec/auth/token/#requesting-a-token
+pub async fn get(req: Request, ctx: RouteContext<Caller>) -> Result<Response> {
+    let url = req.url()?;
+    let mut scopes = Vec::new();
+    let mut offline = false;
+    for (key, value) in url.query_pairs() {
+        match key.as_ref() {
+            "scope" => scopes.extend(parse_scope(&value)),
+            "offline_token" => offline = value == "true",
+            _ => {}
+        }
+    }
+
+    let authorization = req.headers().get("Authorization")?.unwrap_or_default();
+    let credentials = if authorization.is_empty() {
+        Credentials::Anonymous
+    } else {
+        match parse_basic(&authorization) {
+            Some((username, password)) => Credentials::Password { username, password },
+            None => return RegistryError::Unauthorized.to_response(),
+        }
+    };
+    issue(&req, &ctx, credentials, &scopes, offline).await
+}
+
+/// Get a token for the scopes, following OAuth2 with the `password` or the `refresh_token` grant.
+///
+/// The scopes are separated by spaces, and `access_type=offline` asks for a refresh token.
+///
+/// See https://docs.docker.com/registry/spec/auth/oauth/
+pub async fn post(mut req: Request, ctx: RouteContext<Caller>) -> Result<Response> {
+    let form = Url::parse(&format!("https://form/?{}", req.text().await?))?;
+    let param = |name: &str| {
+        form.query_pairs()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.into_owned())
+            .unwrap_or_default()
+    };
+
+    let credentials = match param("grant_type").as_str() {
+        "password" => Credentials::Password {
+            username: param("username"),
+            password: param("password"),
+        },
+        "refresh_token" => Credentials::RefreshToken(param("refresh_token")),
+        _ => return RegistryError::Unsupported.to_response(),
+    };
+    let scopes = param("scope").split(' ').flat_map(parse_scope).collect::<Vec<_>>();
+    let offline = param("access_type") == "offline";
+    issue(&req, &ctx, credentials, &scopes, offline).await
+}
+
+async fn issue(
+    req: &Request,
+    ctx: &RouteContext<Caller>,
+    credentials: Credentials,
+    scopes: &[Access],
+    offline: bool,
+) -> Result<Response> {
+    let config = AuthConfig::from_env(&ctx.env, &req.url()?)?;
+    if config.signing_keys.keys.is_empty() {
+        // Tokens are issued by another token server.
+        return RegistryError::Unsupported.to_response();
+    }
+    let now = Date::now().as_millis() / 1000;
+
+    let grant = match credentials {
+        Credentials::Anonymous => grant_anyone(ctx, scopes).await?,
+        credentials => match authenticate(ctx, &config, credentials, scopes, now).await? {
+            Some(grant) => grant,
+            None => return RegistryError::Unauthorized.to_response(),
+        },
+    };
+    let subject = grant.subject;
+
+    let sign_error = |err| Error::RustError(format!("failed to sign a token: {:?}", err));
+    let token = issuer::issue_access_token(&config, &subject, grant.access, now).map_err(sign_error)?;
+    let refresh_token = if offline && grant.refreshable {
+        Some(issuer::issue_refresh_token(&config, &subject, now).map_err(sign_error)?)
+    } else {
+        None
+    };
+
+    Response::from_json(&TokenResponse {
+        token: token.clone(),
+        access_token: token,
+        expires_in: issuer::ACCESS_TOKEN_TTL,
+        refresh_token,
+    })
+}
+
+/// What the credentials have been verified to be granted.
+#[derive(Default)]
+struct Grant {
+    subject: String,
+    access: Vec<Access>,
+
+    /// Whether a refresh token can be issued, which isn't the case of ID tokens meant to be short-lived.
+    refreshable: bool,
+}
+
+/// Grant callers without credentials what the repositories allow anyone, e.g. pulling public images.
+async fn grant_anyone(ctx: &RouteContext<Caller>, scopes: &[Access]) -> Result<Grant> {
+    let mut access = Vec::new();
+    for scope in scopes.iter().filter(|scope| scope.resource_type == access::REPOSITORY) {
+        let repository_name = scope.name.split('/').next().unwrap_or_default();
+        let control = RepositoryClient::new(&ctx.env, repository_name)?
+            .access_control()
+            .await?;
+        let actions: Vec<&str> = scope
+            .actions
+            .iter()
+            .map(String::as_str)
+            .filter(|action| control.allows_anyone(&scope.name, action))
+            .collect();
+        if !actions.is_empty() {
+            access.push(Access::new(access::REPOSITORY, &scope.name, &actions));
+        }
+    }
+    Ok(Grant {
+        access,
+        ..Grant::default()
+    })
+}
+
+/// Verify the credentials and narrow the requested scopes down to what they are allowed.
+///
+/// Robots log in as `$repository+$name`, and people as `$repository+$owner` with a personal access token. A CI job logs in with the name of the repository and its OIDC ID token
+/// as the password, which is trusted by the rules of the repository.
+async fn authenticate(
+    ctx: &RouteContext<Caller>,
+    config: &AuthConfig,
+    credentials: Credentials,
+    scopes: &[Access],
+    now: u64,
+) -> Result<Option<Grant>> {
+    let (username, password, issued_at) = match credentials {
+        Credentials::Anonymous => return Ok(None),
+        Credentials::Password { username, password } => (username, Some(password), None),
+        Credentials::RefreshToken(token) => match issuer::verify_refresh_token(config, &token, now) {
+            Ok(claims) => (claims.sub, None, claims.iat),
+            Err(_) => return Ok(None),
+        },
+    };
+    let ((repository_name, name), password) = match (parse_username(&username), password) {
+        (Some(parsed), password) => (parsed, password),
+        (None, Some(id_token)) => return Ok(exchange(config, &username, &id_token, scopes, now)),
+        (None, None) => return Ok(None),
+    };
+    let mut client = RepositoryClient::new(&ctx.env, repository_name)?;
+
+    // A personal access token can only be used by its owner, and isn't refreshed.
+    if let Some((id, secret)) = password.as_deref().and_then(parse_token) {
+        let token = match client.get::<PersonalAccessToken>(id).await? {
+            Some(token) if token.owner == name && !token.secret.is_expired(now) && token.secret.verify(secret) => token,
+            _ => return Ok(None),
+        };
+        client.touch::<PersonalAccessToken>(id, now).await?;
+        return Ok(Some(Grant {
+            access: issuer::grant(repository_name, &token.actions, scopes),
+            subject: username.to_string(),
+            refreshable: false,
+        }));
+    }
+
+    let robot = match client.get::<Robot>(name).await? {
+        Some(robot) if !robot.secret.is_expired(now) => robot,
+        _ => return Ok(None),
+    };
+    let verified = match (password, issued_at) {
+        (Some(password), _) => robot.secret.verify(&password),
+        // Rotating the secret revokes the refresh tokens issued with the previous one.
+        (None, Some(issued_at)) => issued_at >= robot.secret.issued_at,
+        (None, None) => false,
+    };
+    if !verified {
+        return Ok(None);
+    }
+    client.touch::<Robot>(name, now).await?;
+    Ok(Some(Grant {
+        access: issuer::grant(repository_name, &robot.actions, scopes),
+        subject: username.to_string(),
+        refreshable: true,
+    }))
+}
+
+/// Exchange an OIDC ID token for access to the repository, as far as its trust rules go.
+fn exchange(config: &AuthConfig, repository_name: &str, id_token: &str, scopes: &[Access], now: u64) -> Option<Grant> {
+    let claims = oidc::verify(id_token, &config.oidc_providers, &config.service, now).ok()?;
+    let rules = config.trust_rules.get(repository_name)?;
+    if !rules.iter().any(|rule| rule.trusts(&claims)) {
+        return None;
+    }
+    Some(Grant {
+        access: oidc::grant(repository_name, rules, &claims, scopes),
+        subject: claims.sub,
+        refreshable: false,
+    })
+}
+
+/// Parse the credentials of a Basic `Authorization` header.
+fn parse_basic(authorization: &str) -> Option<(String, String)> {
+    let encoded = authorization.strip_prefix("Basic ")?;
+    let decoded = String::from_utf8(STANDARD.decode(encoded.trim()).ok()?).ok()?;
+    let (username, password) = decoded.split_once(':')?;
+    Some((username.to_string(), password.to_string()))
+}
+
+/// Scopes which can't be parsed are left out, as no access can be granted on them anyway.
+fn parse_scope(scope: &str) -> Option<Access> {
+    scope.parse().ok()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse_basic_credentials() {
+        assert_eq!(
+            parse_basic("Basic c2FtYWxiYStjaTpzM2NyM3Q6"),
+            Some(("samalba+ci".to_string(), "s3cr3t:".to_string()))
+        );
+        assert_eq!(parse_basic("Bearer c2FtYWxiYStjaTpzM2NyM3Q6"), None);
+        assert_eq!(parse_basic("Basic c2FtYWxiYStjaQ=="), None);
+    }
+}
@@ -1,15 +1,150 @@
-use worker::*;
+use http::Method;
+use worker::Result;
+
+use super::{Context, Request, Response, ResponseBody};
+use crate::backend::Backend;
+use crate::digest::ContentDigest;
+use crate::errors::RegistryError;
+use crate::storage::{self, BlobStorage};
 
 /// Retrieve the blob from the registry identified by `digest`. A `HEAD` request can also be issued to this endpoint to obtain resource information without receiving all data.
 ///
 /// See https://docs.docker.com/registry/spec/api/#get-blob
-pub async fn get(mut _req: Request, _ctx: RouteContext<()>) -> Result<Response> {
-    unimplemented!();
+pub async fn get<S: Backend>(req: Request, ctx: Context<S>) -> Result<Response<S>> {
+    let repository_name = ctx.param("repository_name").unwrap();
+    let image_name = &super::image_name(&ctx);
+    let digest = match ctx.param("digest").unwrap().parse::<ContentDigest>() {
+        Ok(digest) => digest,
+        Err(err) => return err.to_v2_response(),
+    };
+
+    let blobs = ctx.backend.blobs()?;
+    let blob = match storage::find_blob(&blobs, repository_name, image_name, &digest).await? {
+        Some(blob) => blob,
+        None => return RegistryError::BlobUnknown.to_v2_response(),
+    };
+
+    let builder = http::Response::builder()
+        .header("Content-Type", "application/octet-stream")
+        .header("Docker-Content-Digest", digest.to_string())
+        // Blobs are immutable, so the digest identifies the content of any of them.
+        .header("ETag", format!("\"{}\"", digest))
+        .header("Accept-Ranges", "bytes");
+
+    let range = match super::header(&req, "Range") {
+        Some(value) => parse_range(value, blob.size),
+        None => ByteRange::Full,
+    };
+    let (builder, range) = match range {
+        ByteRange::Full => (builder.header("Content-Length", blob.size), None),
+        ByteRange::Partial(start, end) => {
+            let builder = builder
+                .status(206)
+                .header("Content-Length", end - start + 1)
+                .header("Content-Range", format!("bytes {}-{}/{}", start, end, blob.size));
+            (builder, Some((start, end)))
+        }
+        ByteRange::Unsatisfiable => {
+            let builder = builder
+                .status(416)
+                .header("Content-Range", format!("bytes */{}", blob.size));
+            return super::respond(builder, ResponseBody::Empty);
+        }
+    };
+
+    if req.method() == Method::HEAD {
+        return super::respond(builder, ResponseBody::Empty);
+    }
+    let body = match blobs.stream(&blob.key, range).await? {
+        Some(stream) => ResponseBody::Stream(stream),
+        None => ResponseBody::Empty,
+    };
+    super::respond(builder, body)
 }
 
 /// Delete the blob identified by `name` and `digest`
 ///
 /// See https://docs.docker.com/registry/spec/api/#delete-blob
-pub async fn delete(mut _req: Request, _ctx: RouteContext<()>) -> Result<Response> {
-    unimplemented!();
+pub async fn delete<S: Backend>(_req: Request, ctx: Context<S>) -> Result<Response<S>> {
+    let repository_name = ctx.param("repository_name").unwrap();
+    let image_name = &super::image_name(&ctx);
+    let digest = match ctx.param("digest").unwrap().parse::<ContentDigest>() {
+        Ok(digest) => digest,
+        Err(err) => return err.to_v2_response(),
+    };
+
+    // Only the link is removed, the content may still be linked into other images.
+    let blobs = ctx.backend.blobs()?;
+    if !storage::unlink_blob(&blobs, repository_name, image_name, &digest).await? {
+        return RegistryError::BlobUnknown.to_v2_response();
+    }
+
+    let builder = http::Response::builder()
+        .status(202)
+        .header("Docker-Content-Digest", digest.to_string())
+        .header("Content-Length", "0");
+    super::respond(builder, ResponseBody::Empty)
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum ByteRange {
+    Full,
+    /// The inclusive range of bytes to send.
+    Partial(u64, u64),
+    Unsatisfiable,
+}
+
+/// Parse the `Range` header of a request for content of the given size.
+///
+/// Only a single range of bytes is supported, the whole content is sent for anything else.
+/// See https://www.rfc-editor.org/rfc/rfc9110#name-range
+fn parse_range(value: &str, size: u64) -> ByteRange {
+    let spec = match value.trim().strip_prefix("bytes=") {
+        Some(spec) if !spec.contains(',') => spec.trim(),
+        _ => return ByteRange::Full,
+    };
+    let (first, last) = match spec.split_once('-') {
+        Some(bounds) => bounds,
+        None => return ByteRange::Full,
+    };
+
+    let (first, last) = match (first.parse::<u64>(), last.parse::<u64>()) {
+        (Ok(first), Ok(last)) if first <= last => (first, last.min(size.saturating_sub(1))),
+        (Ok(first), Err(_)) if last.is_empty() => (first, size.saturating_sub(1)),
+        // The suffix range of the last `n` bytes.
+        (Err(_), Ok(suffix)) if first.is_empty() && suffix > 0 => (size.saturating_sub(suffix), size.saturating_sub(1)),
+        _ => return ByteRange::Full,
+    };
+    if first < size && first <= last {
+        ByteRange::Partial(first, last)
+    } else {
+        ByteRange::Unsatisfiable
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse_byte_range() {
+        assert_eq!(parse_range("bytes=0-99", 1000), ByteRange::Partial(0, 99));
+        assert_eq!(parse_range("bytes=900-", 1000), ByteRange::Partial(900, 999));
+        assert_eq!(parse_range("bytes=-100", 1000), ByteRange::Partial(900, 999));
+        assert_eq!(parse_range("bytes=990-2000", 1000), ByteRange::Partial(990, 999));
+        assert_eq!(parse_range("bytes=-2000", 1000), ByteRange::Partial(0, 999));
+    }
+
+    #[test]
+    fn unsatisfiable_byte_range() {
+        assert_eq!(parse_range("bytes=1000-", 1000), ByteRange::Unsatisfiable);
+        assert_eq!(parse_range("bytes=0-0", 0), ByteRange::Unsatisfiable);
+    }
+
+    #[test]
+    fn ignore_unsupported_range() {
+        assert_eq!(parse_range("bytes=0-1,5-10", 1000), ByteRange::Full);
+        assert_eq!(parse_range("items=0-1", 1000), ByteRange::Full);
+        assert_eq!(parse_range("bytes=10-5", 1000), ByteRange::Full);
+    }
 }
@@ -1,4 +1,19 @@
-use worker::*;
+use futures_util::StreamExt;
+use worker::{Error, Result};
+
+use super::{Context, Request, Response, ResponseBody};
+use crate::auth::access;
+use crate::backend::Backend;
+use crate::digest::{ContentDigest, ContentHasher};
+use crate::entities::repository::RepositoryEntity;
+use crate::entities::upload_session::{self, Lease, UploadSession, UploadSessionEntity, UPLOAD_TIMEOUT};
+use crate::errors::{RegistryError, DIGEST_MISMATCH};
+use crate::reference;
+use crate::storage::{
+    self,
+    blob_writer::{BlobWriter, WriterState},
+    BlobStorage,
+};
 
 /// Initiate a resumable blob upload.
 ///
@@ -6,9 +21,85 @@ use worker::*;
 ///
 /// Optionally, if the digest parameter is present, the request body will be used to complete the upload in a single request.
 ///
+/// If the `mount` and `from` parameters are present, the blob of another image the caller can pull is linked
+/// without any upload. An upload is initiated instead if it can't be mounted.
+///
 /// See https://docs.docker.com/registry/spec/api/#post-initiate-blob-upload
-pub async fn initiate(mut _req: Request, _ctx: RouteContext<()>) -> Result<Response> {
-    unimplemented!();
+pub async fn initiate<S: Backend>(req: Request, ctx: Context<S>) -> Result<Response<S>> {
+    let repository_name = ctx.param("repository_name").unwrap();
+    let image_name = &super::image_name(&ctx);
+
+    let url = super::url(&req)?;
+    let param = |name: &str| {
+        url.query_pairs()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.into_owned())
+    };
+    let mount = match (param("mount"), param("from")) {
+        (Some(digest), Some(from)) => mount_blob(&ctx, repository_name, image_name, &digest, &from).await?,
+        _ => None,
+    };
+    if let Some(digest) = mount {
+        return blob_created(repository_name, image_name, &digest);
+    }
+
+    let digest = match param("digest").filter(|_| param("mount").is_none()) {
+        Some(digest) => digest,
+        None => {
+            let uuid = uuid::Uuid::new_v4().to_string();
+            let session = UploadSession {
+                repository_name: repository_name.to_string(),
+                image_name: image_name.to_string(),
+                key: storage::upload_key(&uuid),
+                writer: WriterState::default(),
+                expires_at: ctx.now + UPLOAD_TIMEOUT,
+                lease: None,
+                committed: None,
+            };
+            if !ctx.backend.upload_session(&uuid)?.create(&session).await? {
+                return Err(Error::RustError(format!("upload {} has already been initiated", uuid)));
+            }
+
+            let builder = upload_headers(repository_name, image_name, &uuid, session.offset()).status(202);
+            return super::respond(builder, ResponseBody::Empty);
+        }
+    };
+    let digest = match digest.parse::<ContentDigest>() {
+        Ok(digest) => digest,
+        Err(err) => return err.to_v2_response(),
+    };
+
+    let blobs = ctx.backend.blobs()?;
+    let body = req.into_body().into_stream();
+    if storage::has_content(&blobs, &digest).await? {
+        // Content pushed before by any repository is linked without being stored again,
+        // once the body proves that the client holds it.
+        let mut hasher = ContentHasher::new();
+        if let Some(mut stream) = body {
+            while let Some(chunk) = stream.next().await {
+                hasher.update(&chunk?);
+            }
+        }
+        if hasher.digest() != digest {
+            return digest_invalid(&digest);
+        }
+    } else {
+        // Stream the body straight into the storage, the digest is verified before the content becomes visible.
+        let mut writer = BlobWriter::new(&blobs, storage::content_key(&digest));
+        if let Some(stream) = body {
+            writer.write_stream(stream).await?;
+        }
+        if writer.digest() != digest {
+            writer.abort().await?;
+            return digest_invalid(&digest);
+        }
+        writer.commit().await?;
+    }
+    if !storage::link_blob(&blobs, repository_name, image_name, &digest).await? {
+        return Err(collected(&digest));
+    }
+
+    blob_created(repository_name, image_name, &digest)
 }
 
 /// Retrieve status of upload identified by uuid.
@@ -16,29 +107,589 @@ pub async fn initiate(mut _req: Request, _ctx: RouteContext<()>) -> Result<Respo
 /// The primary purpose of this endpoint is to resolve the current status of a resumable upload.
 ///
 /// See https://docs.docker.com/registry/spec/api/#get-blob
-pub async fn get(mut _req: Request, _ctx: RouteContext<()>) -> Result<Response> {
-    unimplemented!();
+pub async fn get<S: Backend>(_req: Request, ctx: Context<S>) -> Result<Response<S>> {
+    let repository_name = ctx.param("repository_name").unwrap();
+    let image_name = &super::image_name(&ctx);
+    let uuid = ctx.param("uuid").unwrap();
+
+    let session = match load_session(&ctx, repository_name, image_name, uuid).await? {
+        Some((_, session)) => session,
+        None => return RegistryError::BlobUploadUnknown.to_v2_response(),
+    };
+
+    let builder = upload_headers(repository_name, image_name, uuid, session.offset()).status(204);
+    super::respond(builder, ResponseBody::Empty)
 }
 
 /// Upload a chunk of data for the specified upload.
 ///
 /// See https://docs.docker.com/registry/spec/api/#patch-blob-upload
-pub async fn append_chunk(mut _req: Request, _ctx: RouteContext<()>) -> Result<Response> {
-    unimplemented!();
+pub async fn append_chunk<S: Backend>(req: Request, ctx: Context<S>) -> Result<Response<S>> {
+    let repository_name = ctx.param("repository_name").unwrap();
+    let image_name = &super::image_name(&ctx);
+    let uuid = ctx.param("uuid").unwrap();
+
+    let (mut entity, mut session) = match load_session(&ctx, repository_name, image_name, uuid).await? {
+        Some(loaded) => loaded,
+        None => return RegistryError::BlobUploadUnknown.to_v2_response(),
+    };
+
+    // Another chunk is being appended concurrently, this one can't be placed after it.
+    let lease = Lease::new(ctx.now);
+    if !entity.acquire(session.offset(), &lease, ctx.now).await? {
+        return RegistryError::RangeInvalid.to_v2_response();
+    }
+
+    let blobs = ctx.backend.blobs()?;
+    let writer = match write_chunk(req, &blobs, &mut session).await {
+        Ok(Some(writer)) => writer,
+        result => {
+            entity.release(&lease.id).await?;
+            result?;
+            return RegistryError::RangeInvalid.to_v2_response();
+        }
+    };
+    session.writer = writer.suspend().await?;
+    session.expires_at = ctx.now + UPLOAD_TIMEOUT;
+
+    // The lease has expired and been taken over by another chunk, whose content this one may have written over.
+    if !entity.save(&lease.id, &session).await? {
+        return RegistryError::RangeInvalid.to_v2_response();
+    }
+
+    let builder = upload_headers(repository_name, image_name, uuid, session.offset()).status(202);
+    super::respond(builder, ResponseBody::Empty)
 }
 
 /// Complete the upload specified by uuid, optionally appending the body as the final chunk.
 ///
 /// See https://docs.docker.com/registry/spec/api/#put-blob-upload
-pub async fn complete(mut _req: Request, _ctx: RouteContext<()>) -> Result<Response> {
-    unimplemented!();
+pub async fn complete<S: Backend>(req: Request, ctx: Context<S>) -> Result<Response<S>> {
+    let repository_name = ctx.param("repository_name").unwrap();
+    let image_name = &super::image_name(&ctx);
+    let uuid = ctx.param("uuid").unwrap();
+
+    let url = super::url(&req)?;
+    let digest = match url.query_pairs().find(|(key, _)| key == "digest") {
+        Some((_, digest)) => digest.parse::<ContentDigest>(),
+        None => Err(RegistryError::DigestInvalid {
+            detail: String::new(),
+            reason: "digest parameter is missing",
+        }),
+    };
+    let digest = match digest {
+        Ok(digest) => digest,
+        Err(err) => return err.to_v2_response(),
+    };
+
+    let (mut entity, mut session) = match load_session(&ctx, repository_name, image_name, uuid).await? {
+        Some(loaded) => loaded,
+        None => return RegistryError::BlobUploadUnknown.to_v2_response(),
+    };
+
+    let blobs = ctx.backend.blobs()?;
+    // A retry after the content has been committed only has to store and link it.
+    if session.committed.is_none() {
+        let lease = Lease::new(ctx.now);
+        if !entity.acquire(session.offset(), &lease, ctx.now).await? {
+            return RegistryError::RangeInvalid.to_v2_response();
+        }
+        let writer = match write_chunk(req, &blobs, &mut session).await {
+            Ok(Some(writer)) => writer,
+            result => {
+                entity.release(&lease.id).await?;
+                result?;
+                return RegistryError::RangeInvalid.to_v2_response();
+            }
+        };
+
+        // The upload is left as it was, so that the client can retry with the right digest.
+        if writer.digest() != digest {
+            entity.release(&lease.id).await?;
+            return digest_invalid(&digest);
+        }
+
+        // The parts now make up the content, only its size is left to report.
+        let size = writer.size();
+        writer.commit().await?;
+        session.writer = WriterState {
+            size,
+            ..Default::default()
+        };
+        session.committed = Some(digest.clone());
+        if !entity.save(&lease.id, &session).await? {
+            return RegistryError::RangeInvalid.to_v2_response();
+        }
+    }
+    if session.committed.as_ref() != Some(&digest) {
+        return digest_invalid(&digest);
+    }
+
+    // The session is deleted last, so that the client can retry if anything fails before the blob is linked.
+    storage::store_upload(&blobs, &digest, &session.key).await?;
+    if !storage::link_blob(&blobs, repository_name, image_name, &digest).await? {
+        return Err(collected(&digest));
+    }
+    entity.delete().await?;
+
+    blob_created(repository_name, image_name, &digest)
 }
 
 /// Cancel outstanding upload processes, releasing associated resources.
 ///
-/// If this is not called, the unfinished uploads will eventually timeout.
+/// If this is not called, the upload is aborted once it hasn't received any chunk for `UPLOAD_TIMEOUT`.
 ///
 /// See https://docs.docker.com/registry/spec/api/#delete-blob-upload
-pub async fn delete(mut _req: Request, _ctx: RouteContext<()>) -> Result<Response> {
-    unimplemented!();
+pub async fn delete<S: Backend>(_req: Request, ctx: Context<S>) -> Result<Response<S>> {
+    let repository_name = ctx.param("repository_name").unwrap();
+    let image_name = &super::image_name(&ctx);
+    let uuid = ctx.param("uuid").unwrap();
+
+    let (mut entity, session) = match load_session(&ctx, repository_name, image_name, uuid).await? {
+        Some(loaded) => loaded,
+        None => return RegistryError::BlobUploadUnknown.to_v2_response(),
+    };
+
+    let blobs = ctx.backend.blobs()?;
+    upload_session::discard(&blobs, &session).await?;
+    entity.delete().await?;
+
+    super::respond(http::Response::builder().status(204), ResponseBody::Empty)
+}
+
+/// Link the blob of the image `from` into `$repository/$image`, if the caller can pull it from there.
+///
+/// Returns `None` if the blob can't be mounted, so that it's uploaded instead.
+async fn mount_blob<S: Backend>(
+    ctx: &Context<S>,
+    repository_name: &str,
+    image_name: &str,
+    digest: &str,
+    from: &str,
+) -> Result<Option<ContentDigest>> {
+    let digest = match digest.parse::<ContentDigest>() {
+        Ok(digest) => digest,
+        Err(_) => return Ok(None),
+    };
+    if reference::validate_name(from).is_err() {
+        return Ok(None);
+    }
+    let (from_repository_name, from_image_name) = from.split_once('/').unwrap();
+
+    // The token only covers the target image, so access to the source is checked the same way as a pull of it.
+    let control = ctx.backend.repository(from_repository_name)?.access_control().await?;
+    let can_pull = control.allows_anyone(from, access::PULL)
+        || (ctx.caller.is_allowed(access::REPOSITORY, from, access::PULL)
+            && control.allows(&ctx.caller, from, access::PULL));
+    if !can_pull {
+        return Ok(None);
+    }
+
+    let blobs = ctx.backend.blobs()?;
+    let linked = storage::find_blob(&blobs, from_repository_name, from_image_name, &digest).await?;
+    if linked.is_none() || !storage::has_content(&blobs, &digest).await? {
+        return Ok(None);
+    }
+    if !storage::link_blob(&blobs, repository_name, image_name, &digest).await? {
+        return Ok(None);
+    }
+    Ok(Some(digest))
+}
+
+/// Load the session of the upload, which must have been initiated in the same repository and not have expired.
+async fn load_session<S: Backend>(
+    ctx: &Context<S>,
+    repository_name: &str,
+    image_name: &str,
+    uuid: &str,
+) -> Result<Option<(S::UploadSession, UploadSession)>> {
+    let entity = ctx.backend.upload_session(uuid)?;
+    Ok(match entity.load().await? {
+        // The alarm which aborts an expired upload may not have run yet.
+        Some(session)
+            if session.repository_name == repository_name
+                && session.image_name == image_name
+                && !session.is_expired(ctx.now) =>
+        {
+            Some((entity, session))
+        }
+        _ => None,
+    })
+}
+
+/// Resume writing the upload and append the request body, which must start where the upload left off.
+///
+/// Returns `None` if the `Content-Range` of the body doesn't match the upload.
+async fn write_chunk<'a, B: BlobStorage>(
+    req: Request,
+    blobs: &'a B,
+    session: &mut UploadSession,
+) -> Result<Option<BlobWriter<'a, B>>> {
+    let range = match super::header(&req, "Content-Range") {
+        Some(value) => match parse_content_range(value) {
+            Some((start, end)) if start == session.offset() => Some((start, end)),
+            _ => return Ok(None),
+        },
+        None => None,
+    };
+
+    let state = std::mem::take(&mut session.writer);
+    let mut writer = BlobWriter::resume(blobs, session.key.clone(), state).await?;
+    if let Some(stream) = req.into_body().into_stream() {
+        writer.write_stream(stream).await?;
+    }
+
+    match range {
+        Some((_, end)) if writer.size() != end + 1 => Ok(None),
+        _ => Ok(Some(writer)),
+    }
+}
+
+/// Parse a `Content-Range` header of the form `<start>-<end>`, where both ends are inclusive.
+fn parse_content_range(value: &str) -> Option<(u64, u64)> {
+    let value = value.trim().trim_start_matches("bytes ");
+    let (start, end) = value.split_once('-')?;
+    let (start, end) = (start.parse::<u64>().ok()?, end.parse::<u64>().ok()?);
+    (start <= end).then_some((start, end))
+}
+
+fn upload_headers(repository_name: &str, image_name: &str, uuid: &str, offset: u64) -> http::response::Builder {
+    http::Response::builder()
+        .header(
+            "Location",
+            format!("/v2/{}/{}/blobs/uploads/{}", repository_name, image_name, uuid),
+        )
+        // The range is inclusive, an empty upload is reported as `0-0` as well.
+        .header("Range", format!("0-{}", offset.saturating_sub(1)))
+        .header("Docker-Upload-UUID", uuid)
+        .header("Content-Length", "0")
+}
+
+/// The content has been collected as garbage right before it was linked, so the client has to push it again.
+fn collected(digest: &ContentDigest) -> Error {
+    Error::RustError(format!("content of {} was collected while being linked", digest))
+}
+
+fn digest_invalid<T>(digest: &ContentDigest) -> Result<http::Response<ResponseBody<T>>> {
+    let err = RegistryError::DigestInvalid {
+        detail: digest.to_string(),
+        reason: DIGEST_MISMATCH,
+    };
+    err.to_v2_response()
+}
+
+fn blob_created<T>(
+    repository_name: &str,
+    image_name: &str,
+    digest: &ContentDigest,
+) -> Result<http::Response<ResponseBody<T>>> {
+    let builder = http::Response::builder()
+        .status(201)
+        .header(
+            "Location",
+            format!("/v2/{}/{}/blobs/{}", repository_name, image_name, digest),
+        )
+        .header("Docker-Content-Digest", digest.to_string())
+        .header("Content-Length", "0");
+    super::respond(builder, ResponseBody::Empty)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::auth::access::Access;
+    use crate::backend::memory::MemoryBackend;
+    use crate::controllers::v2::{blob, testing, RequestBody};
+    use crate::entities::upload_session::LEASE_TIMEOUT;
+    use futures_executor::block_on;
+
+    #[test]
+    fn parse_range() {
+        assert_eq!(parse_content_range("0-1023"), Some((0, 1023)));
+        assert_eq!(parse_content_range("bytes 1024-2047"), Some((1024, 2047)));
+    }
+
+    #[test]
+    fn invalid_range() {
+        assert_eq!(parse_content_range("1024"), None);
+        assert_eq!(parse_content_range("2047-1024"), None);
+        assert_eq!(parse_content_range("0-"), None);
+    }
+
+    fn location(res: &Response<MemoryBackend>) -> String {
+        res.headers()["Location"].to_str().unwrap().to_string()
+    }
+
+    #[test]
+    fn chunked_upload() {
+        let backend = MemoryBackend::default();
+        let name = [("repository_name", "registry"), ("image_name", "team%2Fworker")];
+        block_on(async {
+            let req = testing::request("POST", "/v2/registry/team/worker/blobs/uploads/", RequestBody::empty());
+            let res = initiate(req, testing::context(&backend, &name)).await.unwrap();
+            assert_eq!(res.status(), 202);
+            assert_eq!(res.headers()["Range"], "0-0");
+            let uuid = res.headers()["Docker-Upload-UUID"].to_str().unwrap().to_string();
+            assert_eq!(
+                location(&res),
+                format!("/v2/registry/team/worker/blobs/uploads/{}", uuid)
+            );
+            let params = [name[0], name[1], ("uuid", &uuid)];
+
+            let mut req = testing::request("PATCH", &location(&res), RequestBody::from_bytes(b"hello ".to_vec()));
+            req.headers_mut().insert("Content-Range", "0-5".parse().unwrap());
+            let res = append_chunk(req, testing::context(&backend, &params)).await.unwrap();
+            assert_eq!(res.status(), 202);
+            assert_eq!(res.headers()["Range"], "0-5");
+
+            // A chunk which doesn't start where the upload left off is rejected.
+            let mut req = testing::request("PATCH", &location(&res), RequestBody::from_bytes(b"world".to_vec()));
+            req.headers_mut().insert("Content-Range", "0-4".parse().unwrap());
+            let res = append_chunk(req, testing::context(&backend, &params)).await.unwrap();
+            assert_eq!(res.status(), 416);
+
+            let mut hasher = ContentHasher::new();
+            hasher.update(b"hello world");
+            let digest = hasher.digest();
+            let path = format!("/v2/registry/team/worker/blobs/uploads/{}?digest={}", uuid, digest);
+            let req = testing::request("PUT", &path, RequestBody::from_bytes(b"world".to_vec()));
+            let res = complete(req, testing::context(&backend, &params)).await.unwrap();
+            assert_eq!(res.status(), 201);
+            assert_eq!(location(&res), format!("/v2/registry/team/worker/blobs/{}", digest));
+            assert!(backend.blobs.pending_uploads().is_empty());
+
+            let req = testing::request("GET", &location(&res), RequestBody::empty());
+            let digest = digest.to_string();
+            let params = [name[0], name[1], ("digest", &digest)];
+            let res = blob::get(req, testing::context(&backend, &params)).await.unwrap();
+            assert_eq!(res.status(), 200);
+            assert_eq!(testing::body(res), b"hello world");
+
+            // The session is gone along with the upload.
+            let params = [name[0], name[1], ("uuid", &uuid)];
+            let req = testing::request("GET", "/", RequestBody::empty());
+            let res = get(req, testing::context(&backend, &params)).await.unwrap();
+            assert_eq!(res.status(), 404);
+        });
+    }
+
+    #[test]
+    fn retry_completion_with_right_digest() {
+        let backend = MemoryBackend::default();
+        let name = [("repository_name", "registry"), ("image_name", "team%2Fworker")];
+        block_on(async {
+            let req = testing::request("POST", "/v2/registry/team/worker/blobs/uploads/", RequestBody::empty());
+            let res = initiate(req, testing::context(&backend, &name)).await.unwrap();
+            let uuid = res.headers()["Docker-Upload-UUID"].to_str().unwrap().to_string();
+            let params = [name[0], name[1], ("uuid", &uuid)];
+
+            let mut req = testing::request("PATCH", &location(&res), RequestBody::from_bytes(b"hello ".to_vec()));
+            req.headers_mut().insert("Content-Range", "0-5".parse().unwrap());
+            let res = append_chunk(req, testing::context(&backend, &params)).await.unwrap();
+            assert_eq!(res.status(), 202);
+
+            let mut hasher = ContentHasher::new();
+            hasher.update(b"hello there");
+            let path = format!(
+                "/v2/registry/team/worker/blobs/uploads/{}?digest={}",
+                uuid,
+                hasher.digest()
+            );
+            let req = testing::request("PUT", &path, RequestBody::from_bytes(b"world".to_vec()));
+            let res = complete(req, testing::context(&backend, &params)).await.unwrap();
+            assert_eq!(res.status(), 400);
+
+            // The upload is left where it was before the final chunk.
+            let req = testing::request("GET", "/", RequestBody::empty());
+            let res = get(req, testing::context(&backend, &params)).await.unwrap();
+            assert_eq!(res.status(), 204);
+            assert_eq!(res.headers()["Range"], "0-5");
+
+            let mut hasher = ContentHasher::new();
+            hasher.update(b"hello world");
+            let path = format!(
+                "/v2/registry/team/worker/blobs/uploads/{}?digest={}",
+                uuid,
+                hasher.digest()
+            );
+            let req = testing::request("PUT", &path, RequestBody::from_bytes(b"world".to_vec()));
+            let res = complete(req, testing::context(&backend, &params)).await.unwrap();
+            assert_eq!(res.status(), 201);
+            assert!(backend.upload_session(&uuid).unwrap().load().await.unwrap().is_none());
+        });
+    }
+
+    #[test]
+    fn reject_concurrent_chunk() {
+        let backend = MemoryBackend::default();
+        let name = [("repository_name", "registry"), ("image_name", "team%2Fworker")];
+        block_on(async {
+            let req = testing::request("POST", "/v2/registry/team/worker/blobs/uploads/", RequestBody::empty());
+            let res = initiate(req, testing::context(&backend, &name)).await.unwrap();
+            let uuid = res.headers()["Docker-Upload-UUID"].to_str().unwrap().to_string();
+            let params = [name[0], name[1], ("uuid", &uuid)];
+
+            // Another chunk is being written at the same offset.
+            let mut session = backend.upload_session(&uuid).unwrap();
+            assert!(session.acquire(0, &Lease::new(0), 0).await.unwrap());
+            let patch = || {
+                let mut req = testing::request("PATCH", &location(&res), RequestBody::from_bytes(b"hello".to_vec()));
+                req.headers_mut().insert("Content-Range", "0-4".parse().unwrap());
+                req
+            };
+            let res = append_chunk(patch(), testing::context(&backend, &params))
+                .await
+                .unwrap();
+            assert_eq!(res.status(), 416);
+            assert!(backend.blobs.keys().is_empty());
+
+            // The upload is taken over once the lease has expired.
+            let mut ctx = testing::context(&backend, &params);
+            ctx.now = LEASE_TIMEOUT;
+            let res = append_chunk(patch(), ctx).await.unwrap();
+            assert_eq!(res.status(), 202);
+            assert_eq!(res.headers()["Range"], "0-4");
+            assert!(session.load().await.unwrap().unwrap().lease.is_none());
+        });
+    }
+
+    #[test]
+    fn expire_abandoned_upload() {
+        let backend = MemoryBackend::default();
+        let name = [("repository_name", "registry"), ("image_name", "team%2Fworker")];
+        block_on(async {
+            let req = testing::request("POST", "/v2/registry/team/worker/blobs/uploads/", RequestBody::empty());
+            let res = initiate(req, testing::context(&backend, &name)).await.unwrap();
+            let uuid = res.headers()["Docker-Upload-UUID"].to_str().unwrap().to_string();
+            let params = [name[0], name[1], ("uuid", &uuid)];
+
+            // Each chunk pushes the deadline back.
+            let mut ctx = testing::context(&backend, &params);
+            ctx.now = UPLOAD_TIMEOUT - 1;
+            let mut req = testing::request("PATCH", &location(&res), RequestBody::from_bytes(b"hello ".to_vec()));
+            req.headers_mut().insert("Content-Range", "0-5".parse().unwrap());
+            let res = append_chunk(req, ctx).await.unwrap();
+            assert_eq!(res.status(), 202);
+
+            let mut session = backend.upload_session(&uuid).unwrap();
+            assert_eq!(
+                session.expire(&backend.blobs, UPLOAD_TIMEOUT).await.unwrap(),
+                Some(UPLOAD_TIMEOUT * 2 - 1)
+            );
+            assert!(!backend.blobs.keys().is_empty());
+
+            // The upload is unknown once expired, even before the alarm has aborted it.
+            let mut ctx = testing::context(&backend, &params);
+            ctx.now = UPLOAD_TIMEOUT * 2 - 1;
+            let req = testing::request("GET", "/", RequestBody::empty());
+            let res = get(req, ctx).await.unwrap();
+            assert_eq!(res.status(), 404);
+
+            assert_eq!(
+                session.expire(&backend.blobs, UPLOAD_TIMEOUT * 2 - 1).await.unwrap(),
+                None
+            );
+            assert!(session.load().await.unwrap().is_none());
+            assert!(backend.blobs.keys().is_empty());
+            assert!(backend.blobs.pending_uploads().is_empty());
+        });
+    }
+
+    #[test]
+    fn monolithic_upload_with_wrong_digest() {
+        let backend = MemoryBackend::default();
+        let params = [("repository_name", "registry"), ("image_name", "worker")];
+        block_on(async {
+            let path = format!("/v2/registry/worker/blobs/uploads/?digest=sha256:{}", "0".repeat(64));
+            let req = testing::request("POST", &path, RequestBody::from_bytes(b"hello".to_vec()));
+            let res = initiate(req, testing::context(&backend, &params)).await.unwrap();
+            assert_eq!(res.status(), 400);
+            assert!(backend.blobs.keys().is_empty());
+        });
+    }
+
+    #[test]
+    fn store_content_once() {
+        let backend = MemoryBackend::default();
+        let mut hasher = ContentHasher::new();
+        hasher.update(b"layer");
+        let digest = hasher.digest().to_string();
+        block_on(async {
+            for (repository_name, image_name) in [("staging", "worker"), ("prod", "team%2Fworker")] {
+                let path = format!(
+                    "/v2/{}/{}/blobs/uploads/?digest={}",
+                    repository_name, image_name, digest
+                );
+                let req = testing::request("POST", &path, RequestBody::from_bytes(b"layer".to_vec()));
+                let params = [("repository_name", repository_name), ("image_name", image_name)];
+                let res = initiate(req, testing::context(&backend, &params)).await.unwrap();
+                assert_eq!(res.status(), 201);
+            }
+            let content_key = format!("_blobs/{}", digest);
+            let stored = backend
+                .blobs
+                .keys()
+                .into_iter()
+                .filter(|key| key.starts_with(&content_key));
+            assert_eq!(stored.count(), 3, "the content and a reference of each image");
+
+            // Deleting the blob from one image leaves it to the other.
+            let params = [
+                ("repository_name", "staging"),
+                ("image_name", "worker"),
+                ("digest", &digest),
+            ];
+            let req = testing::request("DELETE", "/", RequestBody::empty());
+            let res = blob::delete(req, testing::context(&backend, &params)).await.unwrap();
+            assert_eq!(res.status(), 202);
+            let req = testing::request("GET", "/", RequestBody::empty());
+            let res = blob::get(req, testing::context(&backend, &params)).await.unwrap();
+            assert_eq!(res.status(), 404);
+
+            let params = [
+                ("repository_name", "prod"),
+                ("image_name", "team%2Fworker"),
+                ("digest", &digest),
+            ];
+            let req = testing::request("GET", "/", RequestBody::empty());
+            let res = blob::get(req, testing::context(&backend, &params)).await.unwrap();
+            assert_eq!(testing::body(res), b"layer");
+        });
+    }
+
+    #[test]
+    fn mount_from_another_repository() {
+        let backend = MemoryBackend::default();
+        let mut hasher = ContentHasher::new();
+        hasher.update(b"layer");
+        let digest = hasher.digest().to_string();
+        let params = [("repository_name", "prod"), ("image_name", "worker")];
+        let mount = |from: &str, can_pull: bool| {
+            let mut ctx = testing::context(&backend, &params);
+            if can_pull {
+                let pull = Access::new(access::REPOSITORY, "staging/*", &[access::PULL]);
+                ctx.caller.access.push(pull);
+            }
+            let path = format!("/v2/prod/worker/blobs/uploads/?mount={}&from={}", digest, from);
+            initiate(testing::request("POST", &path, RequestBody::empty()), ctx)
+        };
+        block_on(async {
+            let path = format!("/v2/staging/worker/blobs/uploads/?digest={}", digest);
+            let req = testing::request("POST", &path, RequestBody::from_bytes(b"layer".to_vec()));
+            let staging = [("repository_name", "staging"), ("image_name", "worker")];
+            initiate(req, testing::context(&backend, &staging)).await.unwrap();
+
+            let res = mount("staging/worker", true).await.unwrap();
+            assert_eq!(res.status(), 201);
+            assert_eq!(location(&res), format!("/v2/prod/worker/blobs/{}", digest));
+
+            // A caller which can't pull the source gets an upload instead.
+            let res = mount("staging/worker", false).await.unwrap();
+            assert_eq!(res.status(), 202);
+
+            // So does a blob which the source doesn't have.
+            let res = mount("staging/other", true).await.unwrap();
+            assert_eq!(res.status(), 202);
+        });
+    }
 }
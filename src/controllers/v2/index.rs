@@ -1,23 +1,290 @@
-use worker::*;
+use serde::Serialize;
+use std::collections::HashMap;
+use worker::{Result, Url};
+
+use super::{Context, Request, Response, ResponseBody};
+use crate::auth::policy::AccessControl;
+use crate::auth::{access, Caller};
+use crate::backend::Backend;
+use crate::entities::catalog::CatalogEntity;
+use crate::entities::repository::RepositoryEntity;
+use crate::errors::RegistryError;
+use crate::storage::{self, BlobStorage};
+
+/// The number of entries returned when `n` isn't given, which is also the most listed at once.
+const MAX_PAGE_SIZE: usize = 1000;
+
+/// The most calls to Durable Objects listing the catalog makes, i.e. pages of the catalog and access controls of
+/// repositories, so that a request stays well within the subrequest limit of a worker.
+const MAX_CATALOG_LOOKUPS: usize = 40;
 
 /// Check that the endpoint implements Docker Registry API V2.
 ///
 /// See https://docs.docker.com/registry/spec/api/#get-base
-pub async fn get_base(mut _req: Request, _ctx: RouteContext<()>) -> Result<Response> {
-    // FIXME: implement auth methods
-    Response::ok("")
+///
+/// Requests are authenticated before being routed, so a client which reaches here holds a valid token.
+pub async fn get_base<S: Backend>(_req: Request, _ctx: Context<S>) -> Result<Response<S>> {
+    super::respond(http::Response::builder(), ResponseBody::Empty)
 }
 
-/// Fetch the tags under the repository identified by `name`.
+#[derive(Debug, Serialize)]
+struct TagList {
+    name: String,
+    tags: Vec<String>,
+}
+
+/// Fetch the tags under the repository identified by `name`, in lexical order.
+///
+/// Tags are listed by pages of up to `n` entries, starting after the `last` tag of the previous page.
+/// The `Link` header refers to the next page if there are more tags left.
 ///
 /// See https://docs.docker.com/registry/spec/api/#get-tags
-pub async fn get_tags(mut _req: Request, _ctx: RouteContext<()>) -> Result<Response> {
-    unimplemented!();
+pub async fn get_tags<S: Backend>(req: Request, ctx: Context<S>) -> Result<Response<S>> {
+    let repository_name = ctx.param("repository_name").unwrap();
+    let image_name = &super::image_name(&ctx);
+
+    let url = super::url(&req)?;
+    let (n, last) = match parse_pagination(&url) {
+        Ok(pagination) => pagination,
+        Err(err) => return err.to_v2_response(),
+    };
+
+    let prefix = storage::tags_prefix(repository_name, image_name);
+    let (keys, truncated) = if n == 0 {
+        (Vec::new(), false)
+    } else {
+        let start_after = last.as_ref().map(|last| format!("{}{}", prefix, last));
+        ctx.backend.blobs()?.list(&prefix, start_after.as_deref(), n).await?
+    };
+    let tags: Vec<String> = keys
+        .iter()
+        .filter_map(|key| key.strip_prefix(&prefix))
+        .map(str::to_string)
+        .collect();
+
+    // Nothing has ever been tagged under an image which doesn't exist.
+    if tags.is_empty() && last.is_none() && n > 0 {
+        return RegistryError::NameUnknown.to_v2_response();
+    }
+
+    let mut builder = http::Response::builder();
+    if let (true, Some(last)) = (truncated, tags.last()) {
+        let path = format!("/v2/{}/{}/tags/list", repository_name, image_name);
+        builder = builder.header("Link", next_link(&path, n, last));
+    }
+    let tag_list = TagList {
+        name: format!("{}/{}", repository_name, image_name),
+        tags,
+    };
+    super::respond_json(builder, &tag_list)
+}
+
+#[derive(Debug, Serialize)]
+struct Catalog {
+    repositories: Vec<String>,
 }
 
 /// Retrieve a sorted, json list of repositories available in the registry.
 ///
+/// Each entry is the `$repository/$image` name of an image which holds a manifest and the caller can pull,
+/// listed by pages the same way as tags. A page may hold fewer names than asked for, even none, when most of the
+/// catalog is hidden from the caller, in which case the `Link` header still leads to the next one.
+///
 /// See https://docs.docker.com/registry/spec/api/#get-catalog
-pub async fn get_catalog(mut _req: Request, _ctx: RouteContext<()>) -> Result<Response> {
-    unimplemented!();
+pub async fn get_catalog<S: Backend>(req: Request, ctx: Context<S>) -> Result<Response<S>> {
+    let url = super::url(&req)?;
+    let (n, last) = match parse_pagination(&url) {
+        Ok(pagination) => pagination,
+        Err(err) => return err.to_v2_response(),
+    };
+
+    // Names the caller can't pull are skipped, so pages are listed until enough names are visible.
+    // Once the lookups run out, fewer names are returned and the next page starts after the last one looked at.
+    let catalog = ctx.backend.catalog()?;
+    let mut controls = HashMap::new();
+    let mut repositories = Vec::new();
+    let mut cursor = last;
+    let mut truncated = n > 0;
+    let mut pages = 0;
+    while truncated && repositories.len() < n && pages + controls.len() < MAX_CATALOG_LOOKUPS {
+        let page = catalog.list(cursor.as_deref(), MAX_PAGE_SIZE).await?;
+        pages += 1;
+        truncated = page.truncated;
+        for name in page.names {
+            let is_visible = is_visible(&ctx.caller, &name);
+            let repository_name = name.split('/').next().unwrap_or_default();
+            let needs_lookup = is_visible && !controls.contains_key(repository_name);
+            if repositories.len() == n || (needs_lookup && pages + controls.len() >= MAX_CATALOG_LOOKUPS) {
+                truncated = true;
+                break;
+            }
+            if is_visible && is_allowed_by_repository(&ctx, &mut controls, &name).await? {
+                repositories.push(name.clone());
+            }
+            cursor = Some(name);
+        }
+    }
+
+    let mut builder = http::Response::builder();
+    if let (true, Some(last)) = (truncated, cursor.as_deref()) {
+        builder = builder.header("Link", next_link("/v2/_catalog", n, last));
+    }
+    super::respond_json(builder, &Catalog { repositories })
+}
+
+/// Whether the caller can see the `$repository/$image` name in the catalog.
+fn is_visible(caller: &Caller, name: &str) -> bool {
+    caller.is_allowed(access::REGISTRY, "catalog", "*") || caller.is_allowed(access::REPOSITORY, name, access::PULL)
+}
+
+/// Whether the repository of the `$repository/$image` name, e.g. by its access policy, lets the caller pull it.
+///
+/// What decides access to each repository is cached for the time of the request.
+async fn is_allowed_by_repository<S: Backend>(
+    ctx: &Context<S>,
+    controls: &mut HashMap<String, AccessControl>,
+    name: &str,
+) -> Result<bool> {
+    let repository_name = name.split('/').next().unwrap_or_default();
+    if !controls.contains_key(repository_name) {
+        let control = ctx.backend.repository(repository_name)?.access_control().await?;
+        controls.insert(repository_name.to_string(), control);
+    }
+    Ok(controls[repository_name].allows(&ctx.caller, name, access::PULL))
+}
+
+/// Parse the `n` and `last` query parameters of a paginated list.
+///
+/// `n` is capped to `MAX_PAGE_SIZE`, and defaults to it.
+fn parse_pagination(url: &Url) -> std::result::Result<(usize, Option<String>), RegistryError> {
+    let mut n = MAX_PAGE_SIZE;
+    let mut last = None;
+    for (key, value) in url.query_pairs() {
+        match key.as_ref() {
+            "n" => {
+                n = value
+                    .parse::<usize>()
+                    .map_err(|_| RegistryError::PaginationNumberInvalid)?
+                    .min(MAX_PAGE_SIZE)
+            }
+            "last" if !value.is_empty() => last = Some(value.into_owned()),
+            _ => {}
+        }
+    }
+    Ok((n, last))
+}
+
+/// The RFC 5988 `Link` header to the page after `last`.
+fn next_link(path: &str, n: usize, last: &str) -> String {
+    let mut url = Url::parse("http://localhost").unwrap();
+    url.set_path(path);
+    url.query_pairs_mut()
+        .append_pair("n", &n.to_string())
+        .append_pair("last", last);
+    format!("<{}?{}>; rel=\"next\"", url.path(), url.query().unwrap_or_default())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::auth::policy::Policy;
+    use crate::backend::memory::MemoryBackend;
+    use crate::controllers::v2::{testing, RequestBody};
+    use crate::entities::catalog::CatalogEntity;
+    use crate::entities::repository::RepositoryEntity;
+    use futures_executor::block_on;
+
+    fn pagination(query: &str) -> std::result::Result<(usize, Option<String>), RegistryError> {
+        let url = Url::parse(&format!(
+            "https://registry.example.com/v2/library/nginx/tags/list{}",
+            query
+        ))
+        .unwrap();
+        parse_pagination(&url)
+    }
+
+    #[test]
+    fn parse_pagination_parameters() {
+        assert_eq!(pagination("").ok(), Some((MAX_PAGE_SIZE, None)));
+        assert_eq!(pagination("?n=10&last=v1.0").ok(), Some((10, Some("v1.0".to_string()))));
+        assert_eq!(pagination("?n=0").ok(), Some((0, None)));
+        assert_eq!(pagination("?n=100000").ok(), Some((MAX_PAGE_SIZE, None)));
+    }
+
+    #[test]
+    fn invalid_pagination_number() {
+        assert!(matches!(
+            pagination("?n=-1"),
+            Err(RegistryError::PaginationNumberInvalid)
+        ));
+        assert!(matches!(
+            pagination("?n=ten"),
+            Err(RegistryError::PaginationNumberInvalid)
+        ));
+    }
+
+    #[test]
+    fn catalog_visibility() {
+        let caller = Caller {
+            subject: "robot".to_string(),
+            groups: vec![],
+            access: vec![access::Access::new(access::REPOSITORY, "samalba/*", &[access::PULL])],
+        };
+        assert!(is_visible(&caller, "samalba/my-app"));
+        assert!(!is_visible(&caller, "other/my-app"));
+
+        let caller = Caller {
+            subject: "admin".to_string(),
+            groups: vec![],
+            access: vec![access::Access::new(access::REGISTRY, "catalog", &["*"])],
+        };
+        assert!(is_visible(&caller, "other/my-app"));
+    }
+
+    #[test]
+    fn bound_catalog_lookups() {
+        let backend = MemoryBackend::default();
+        let policy: Policy = serde_json::from_value(serde_json::json!({
+            "statements": [{ "principals": ["someone"], "actions": ["pull"] }]
+        }))
+        .unwrap();
+        block_on(async {
+            // Only the last image can be pulled by anyone but `someone`.
+            let mut catalog = backend.catalog().unwrap();
+            for i in 0..60 {
+                let repository_name = format!("r{:02}", i);
+                catalog.add(&repository_name, "app").await.unwrap();
+                if i < 59 {
+                    let mut repository = backend.repository(&repository_name).unwrap();
+                    repository.put_policy(&policy).await.unwrap();
+                }
+            }
+
+            let mut path = "/v2/_catalog?n=1".to_string();
+            let mut requests = 0;
+            let repositories = loop {
+                requests += 1;
+                let req = testing::request("GET", &path, RequestBody::empty());
+                let res = get_catalog(req, testing::context(&backend, &[])).await.unwrap();
+                let link = res.headers().get("Link").map(|link| link.to_str().unwrap().to_string());
+                let catalog: serde_json::Value = serde_json::from_slice(&testing::body(res)).unwrap();
+                match link {
+                    Some(link) if catalog["repositories"].as_array().unwrap().is_empty() => {
+                        path = link[1..link.find('>').unwrap()].to_string();
+                    }
+                    _ => break catalog["repositories"].clone(),
+                }
+            };
+            assert_eq!(repositories, serde_json::json!(["r59/app"]));
+            assert_eq!(requests, 2);
+        });
+    }
+
+    #[test]
+    fn link_next_page() {
+        assert_eq!(
+            next_link("/v2/library/nginx/tags/list", 10, "v1.0"),
+            "</v2/library/nginx/tags/list?n=10&last=v1.0>; rel=\"next\""
+        );
+    }
 }
@@ -1,16 +1,427 @@
-use worker::*;
+use http::Method;
+use worker::Result;
 
+use super::{Context, Request, Response, ResponseBody};
+use crate::backend::Backend;
+use crate::digest::{ContentDigest, ContentHasher};
+use crate::entities::catalog::CatalogEntity;
+use crate::errors::{RegistryError, DIGEST_MISMATCH};
+use crate::media::Manifest;
+use crate::reference::Reference;
+use crate::storage::{self, BlobStorage, Metadata};
+
+/// The platform served to clients which can't handle a manifest list or an image index pushed under a tag.
+const DEFAULT_PLATFORM: (&str, &str) = ("linux", "amd64");
+
+/// Fetch the manifest identified by `name` and `reference` where `reference` can be a tag or digest.
+/// A `HEAD` request can also be issued to this endpoint to obtain resource information without receiving all data.
+///
 /// See https://docs.docker.com/registry/spec/api/#get-manifest
-pub async fn get(mut _req: Request, _ctx: RouteContext<()>) -> Result<Response> {
-    unimplemented!();
+pub async fn get<S: Backend>(req: Request, ctx: Context<S>) -> Result<Response<S>> {
+    let repository_name = ctx.param("repository_name").unwrap();
+    let image_name = &super::image_name(&ctx);
+    let reference = ctx.param("reference").unwrap();
+
+    let reference = match Reference::parse(reference) {
+        Ok(reference) => reference,
+        Err(err) => return err.to_v2_response(),
+    };
+    let blobs = ctx.backend.blobs()?;
+    let is_tag = matches!(reference, Reference::Tag(_));
+    let mut digest = match reference {
+        Reference::Tag(tag) => match storage::find_tag(&blobs, repository_name, image_name, tag).await? {
+            Some(digest) => match digest.parse::<ContentDigest>() {
+                Ok(digest) => digest,
+                Err(err) => return err.to_v2_response(),
+            },
+            None => return RegistryError::ManifestUnknown.to_v2_response(),
+        },
+        Reference::Digest(digest) => digest,
+    };
+    let (mut media_type, mut body) = match storage::find_manifest(&blobs, repository_name, image_name, &digest).await? {
+        Some(manifest) => manifest,
+        None => return RegistryError::ManifestUnknown.to_v2_response(),
+    };
+
+    let accepted_media_types = parse_accept(super::header(&req, "Accept").unwrap_or_default());
+    if !is_acceptable(&accepted_media_types, &media_type) {
+        // A client which doesn't know manifest lists can still pull the image of the default platform by its tag,
+        // but the content of a digest can't be substituted.
+        let (os, architecture) = DEFAULT_PLATFORM;
+        let platform_digest = match Manifest::parse(&media_type, &String::from_utf8_lossy(&body)) {
+            Ok(manifest) if is_tag => manifest.platform_manifest(os, architecture).cloned(),
+            _ => None,
+        };
+        let platform_manifest = match platform_digest {
+            Some(platform_digest) => {
+                digest = platform_digest;
+                storage::find_manifest(&blobs, repository_name, image_name, &digest).await?
+            }
+            None => None,
+        };
+        match platform_manifest {
+            Some(manifest) if is_acceptable(&accepted_media_types, &manifest.0) => (media_type, body) = manifest,
+            _ => return RegistryError::ManifestUnknown.to_v2_response(),
+        }
+    }
+
+    let builder = http::Response::builder()
+        .header("Content-Type", media_type)
+        .header("Content-Length", body.len())
+        .header("Docker-Content-Digest", digest.to_string())
+        .header("ETag", format!("\"{}\"", digest));
+
+    if req.method() == Method::HEAD {
+        return super::respond(builder, ResponseBody::Empty);
+    }
+    super::respond(builder, ResponseBody::Bytes(body))
 }
 
+/// Put the manifest identified by `name` and `reference` where `reference` can be a tag or digest.
+///
 /// See https://docs.docker.com/registry/spec/api/#put-manifest
-pub async fn put(mut _req: Request, _ctx: RouteContext<()>) -> Result<Response> {
-    unimplemented!();
+pub async fn put<S: Backend>(req: Request, ctx: Context<S>) -> Result<Response<S>> {
+    let repository_name = ctx.param("repository_name").unwrap();
+    let image_name = &super::image_name(&ctx);
+    let reference = match Reference::parse(ctx.param("reference").unwrap()) {
+        Ok(reference) => reference,
+        Err(err) => return err.to_v2_response(),
+    };
+
+    // Parameters such as `charset` aren't kept, so that the manifest is served to clients accepting the bare type.
+    let content_type = super::header(&req, "Content-Type").unwrap_or_default();
+    let media_type = content_type.split(';').next().unwrap_or_default().trim().to_string();
+    let body = req.into_body().bytes().await?;
+    let manifest = std::str::from_utf8(&body)
+        .map_err(|err| RegistryError::ManifestInvalid {
+            detail: err.to_string(),
+        })
+        .and_then(|json| Manifest::parse(&media_type, json));
+    let manifest = match manifest {
+        Ok(manifest) => manifest,
+        Err(err) => return err.to_v2_response(),
+    };
+
+    // The digest is computed over the exact bytes received, which is what clients pull and verify.
+    let mut hasher = ContentHasher::new();
+    hasher.update(&body);
+    let digest = hasher.digest();
+
+    let tag = match reference {
+        Reference::Digest(reference) if reference == digest => None,
+        Reference::Digest(reference) => {
+            return RegistryError::DigestInvalid {
+                detail: reference.to_string(),
+                reason: DIGEST_MISMATCH,
+            }
+            .to_v2_response()
+        }
+        Reference::Tag(tag) => Some(tag),
+    };
+    // A schema1 manifest names its own tag, which must be the one it's pushed under.
+    if let (Manifest::V1(manifest), Some(tag)) = (&manifest, tag) {
+        if manifest.tag != tag {
+            return RegistryError::TagInvalid.to_v2_response();
+        }
+    }
+
+    // Every referenced content must have been pushed to the same repository beforehand.
+    let blobs = ctx.backend.blobs()?;
+    for blob_digest in manifest.blobs() {
+        if storage::find_blob(&blobs, repository_name, image_name, blob_digest)
+            .await?
+            .is_none()
+        {
+            return RegistryError::ManifestBlobUnknown.to_v2_response();
+        }
+    }
+    for manifest_digest in manifest.manifests() {
+        let key = storage::manifest_key(repository_name, image_name, manifest_digest);
+        if blobs.head(&key).await?.is_none() {
+            return RegistryError::ManifestBlobUnknown.to_v2_response();
+        }
+    }
+
+    // The referrer is described before the body is handed over to the storage.
+    let referrer = manifest.subject().map(|subject| {
+        let descriptor = manifest.describe(&media_type, digest.clone(), body.len() as u64);
+        (subject.digest.clone(), descriptor)
+    });
+
+    let metadata = Metadata {
+        content_type: Some(media_type),
+        ..Default::default()
+    };
+    blobs
+        .put(
+            &storage::manifest_key(repository_name, image_name, &digest),
+            body,
+            metadata,
+        )
+        .await?;
+    if let Some((subject, descriptor)) = &referrer {
+        storage::put_referrer(&blobs, repository_name, image_name, subject, descriptor).await?;
+    }
+    if let Some(tag) = tag {
+        storage::put_tag(&blobs, repository_name, image_name, tag, &digest).await?;
+    }
+    ctx.backend.catalog()?.add(repository_name, image_name).await?;
+
+    let mut builder = http::Response::builder()
+        .status(201)
+        .header(
+            "Location",
+            format!("/v2/{}/{}/manifests/{}", repository_name, image_name, digest),
+        )
+        .header("Docker-Content-Digest", digest.to_string())
+        .header("Content-Length", "0");
+    if let Some((subject, _)) = &referrer {
+        // Tells the client that the referrers API keeps track of the subject, so no fallback tag is needed.
+        builder = builder.header("OCI-Subject", subject.to_string());
+    }
+    super::respond(builder, ResponseBody::Empty)
 }
 
+/// Delete the manifest identified by `name` and `reference`.
+///
 /// See https://docs.docker.com/registry/spec/api/#delete-manifest
-pub async fn delete(mut _req: Request, _ctx: RouteContext<()>) -> Result<Response> {
-    unimplemented!();
+pub async fn delete<S: Backend>(_req: Request, ctx: Context<S>) -> Result<Response<S>> {
+    let repository_name = ctx.param("repository_name").unwrap();
+    let image_name = &super::image_name(&ctx);
+    let reference = ctx.param("reference").unwrap();
+
+    let blobs = ctx.backend.blobs()?;
+    let digest = match Reference::parse(reference) {
+        // Deleting a tag leaves the manifest it points to in place.
+        Ok(Reference::Tag(tag)) => {
+            if !storage::delete_tag(&blobs, repository_name, image_name, tag).await? {
+                return RegistryError::ManifestUnknown.to_v2_response();
+            }
+            return super::respond(http::Response::builder().status(202), ResponseBody::Empty);
+        }
+        Ok(Reference::Digest(digest)) => digest,
+        Err(err) => return err.to_v2_response(),
+    };
+    let (media_type, body) = match storage::find_manifest(&blobs, repository_name, image_name, &digest).await? {
+        Some(manifest) => manifest,
+        None => return RegistryError::ManifestUnknown.to_v2_response(),
+    };
+
+    // Tags must not be left pointing to a manifest which is gone, so they go first in case anything fails.
+    storage::untag_manifest(&blobs, repository_name, image_name, &digest).await?;
+    if let Ok(manifest) = Manifest::parse(&media_type, &String::from_utf8_lossy(&body)) {
+        if let Some(subject) = manifest.subject() {
+            let key = storage::referrer_key(repository_name, image_name, &subject.digest, &digest);
+            blobs.delete(&key).await?;
+        }
+    }
+    blobs
+        .delete(&storage::manifest_key(repository_name, image_name, &digest))
+        .await?;
+
+    // The image leaves the catalog along with its last manifest.
+    let prefix = storage::manifests_prefix(repository_name, image_name);
+    let (remaining, _) = blobs.list(&prefix, None, 1).await?;
+    if remaining.is_empty() {
+        ctx.backend.catalog()?.remove(repository_name, image_name).await?;
+    }
+
+    super::respond(http::Response::builder().status(202), ResponseBody::Empty)
+}
+
+/// Media types listed in the `Accept` header, without their parameters.
+fn parse_accept(accept: &str) -> Vec<&str> {
+    accept
+        .split(',')
+        .filter_map(|value| value.split(';').next())
+        .map(str::trim)
+        .filter(|media_type| !media_type.is_empty())
+        .collect()
+}
+
+/// Whether the media type can be sent to a client, which accepts anything if it doesn't tell.
+///
+/// Ranges such as `application/*` are accepted as well, and parameters of the media type are ignored.
+fn is_acceptable(accepted_media_types: &[&str], media_type: &str) -> bool {
+    let media_type = media_type.split(';').next().unwrap_or_default().trim();
+    accepted_media_types.is_empty()
+        || accepted_media_types
+            .iter()
+            .any(|accepted| match accepted.strip_suffix("/*") {
+                Some("*") => true,
+                Some(range) => media_type.split_once('/').map_or(false, |(main, _)| main == range),
+                None => *accepted == media_type,
+            })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::backend::memory::MemoryBackend;
+    use crate::controllers::v2::{blob_upload, index, testing, RequestBody};
+    use crate::media::oci_image_manifest::OciImageManifest;
+    use crate::media::{manifest_list::ManifestList, manifest_v1::ManifestV1, manifest_v2::ManifestV2};
+    use futures_executor::block_on;
+
+    #[test]
+    fn negotiate_media_type() {
+        let accept = format!("{}, {}; q=0.5", ManifestV2::MIME_TYPE, ManifestV1::SIGNED_MIME_TYPE);
+        let accepted_media_types = parse_accept(&accept);
+        assert_eq!(
+            accepted_media_types,
+            vec![ManifestV2::MIME_TYPE, ManifestV1::SIGNED_MIME_TYPE]
+        );
+        assert!(is_acceptable(&accepted_media_types, ManifestV2::MIME_TYPE));
+        assert!(!is_acceptable(&accepted_media_types, ManifestList::MIME_TYPE));
+
+        assert!(is_acceptable(&parse_accept(""), ManifestList::MIME_TYPE));
+        assert!(is_acceptable(&parse_accept("*/*"), ManifestList::MIME_TYPE));
+        assert!(is_acceptable(&parse_accept("application/*"), ManifestList::MIME_TYPE));
+        assert!(!is_acceptable(&parse_accept("text/*"), ManifestList::MIME_TYPE));
+
+        let media_type = format!("{}; charset=utf-8", ManifestV2::MIME_TYPE);
+        assert!(is_acceptable(&accepted_media_types, &media_type));
+    }
+
+    const NAME: [(&str, &str); 2] = [("repository_name", "registry"), ("image_name", "team%2Fworker")];
+
+    fn digest_of(content: &[u8]) -> ContentDigest {
+        let mut hasher = ContentHasher::new();
+        hasher.update(content);
+        hasher.digest()
+    }
+
+    async fn push_blob(backend: &MemoryBackend, content: &[u8]) {
+        let path = format!("/v2/registry/team/worker/blobs/uploads/?digest={}", digest_of(content));
+        let req = testing::request("POST", &path, RequestBody::from_bytes(content.to_vec()));
+        let res = blob_upload::initiate(req, testing::context(backend, &NAME))
+            .await
+            .unwrap();
+        assert_eq!(res.status(), 201);
+    }
+
+    async fn push_manifest(backend: &MemoryBackend, reference: &str, manifest: &str) -> Response<MemoryBackend> {
+        let path = format!("/v2/registry/team/worker/manifests/{}", reference);
+        let mut req = testing::request("PUT", &path, RequestBody::from_bytes(manifest.as_bytes().to_vec()));
+        req.headers_mut()
+            .insert("Content-Type", OciImageManifest::MIME_TYPE.parse().unwrap());
+        let params = [NAME[0], NAME[1], ("reference", reference)];
+        put(req, testing::context(backend, &params)).await.unwrap()
+    }
+
+    async fn list(backend: &MemoryBackend, path: &str) -> serde_json::Value {
+        let req = testing::request("GET", path, RequestBody::empty());
+        let res = if path == "/v2/_catalog" {
+            index::get_catalog(req, testing::context(backend, &[])).await.unwrap()
+        } else {
+            index::get_tags(req, testing::context(backend, &NAME)).await.unwrap()
+        };
+        serde_json::from_slice(&testing::body(res)).unwrap()
+    }
+
+    #[test]
+    fn push_and_pull() {
+        let backend = MemoryBackend::default();
+        let (config, layer) = (b"{}".as_slice(), b"layer".as_slice());
+        let manifest = format!(
+            r#"{{"schemaVersion":2,"mediaType":"{}","config":{{"mediaType":"application/vnd.oci.image.config.v1+json","digest":"{}","size":2}},"layers":[{{"mediaType":"application/vnd.oci.image.layer.v1.tar","digest":"{}","size":5}}]}}"#,
+            OciImageManifest::MIME_TYPE,
+            digest_of(config),
+            digest_of(layer)
+        );
+        let digest = digest_of(manifest.as_bytes()).to_string();
+        block_on(async {
+            // Blobs must be pushed before the manifest which refers to them.
+            let res = push_manifest(&backend, "v1", &manifest).await;
+            assert_eq!(res.status(), 400);
+
+            push_blob(&backend, config).await;
+            push_blob(&backend, layer).await;
+            let res = push_manifest(&backend, "v1", &manifest).await;
+            assert_eq!(res.status(), 201);
+            assert_eq!(res.headers()["Docker-Content-Digest"], digest.as_str());
+
+            let mut req = testing::request("GET", "/v2/registry/team/worker/manifests/v1", RequestBody::empty());
+            req.headers_mut()
+                .insert("Accept", OciImageManifest::MIME_TYPE.parse().unwrap());
+            let params = [NAME[0], NAME[1], ("reference", "v1")];
+            let res = get(req, testing::context(&backend, &params)).await.unwrap();
+            assert_eq!(res.status(), 200);
+            assert_eq!(res.headers()["Content-Type"], OciImageManifest::MIME_TYPE);
+            assert_eq!(testing::body(res), manifest.as_bytes());
+
+            let res = push_manifest(&backend, "v2", &manifest).await;
+            assert_eq!(res.status(), 201);
+            assert_eq!(
+                list(&backend, "/v2/registry/team/worker/tags/list").await,
+                serde_json::json!({"name": "registry/team/worker", "tags": ["v1", "v2"]})
+            );
+            assert_eq!(
+                list(&backend, "/v2/_catalog").await,
+                serde_json::json!({"repositories": ["registry/team/worker"]})
+            );
+
+            // The image leaves the catalog along with its last manifest.
+            let req = testing::request("DELETE", "/", RequestBody::empty());
+            let params = [NAME[0], NAME[1], ("reference", digest.as_str())];
+            let res = delete(req, testing::context(&backend, &params)).await.unwrap();
+            assert_eq!(res.status(), 202);
+            assert_eq!(
+                list(&backend, "/v2/_catalog").await,
+                serde_json::json!({"repositories": []})
+            );
+            // Nor are its tags left pointing to it.
+            let blobs = backend.blobs().unwrap();
+            for tag in ["v1", "v2"] {
+                let tag = storage::find_tag(&blobs, "registry", "team/worker", tag).await.unwrap();
+                assert_eq!(tag, None);
+            }
+            assert!(!blobs.keys().iter().any(|key| key.contains("/tagged/")));
+            let req = testing::request("GET", "/", RequestBody::empty());
+            let res = get(req, testing::context(&backend, &params)).await.unwrap();
+            assert_eq!(res.status(), 404);
+        });
+    }
+
+    #[test]
+    fn delete_moved_tag() {
+        let backend = MemoryBackend::default();
+        let config = b"{}".as_slice();
+        let manifest_of = |layer: &[u8]| {
+            format!(
+                r#"{{"schemaVersion":2,"mediaType":"{}","config":{{"mediaType":"application/vnd.oci.image.config.v1+json","digest":"{}","size":2}},"layers":[{{"mediaType":"application/vnd.oci.image.layer.v1.tar","digest":"{}","size":{}}}]}}"#,
+                OciImageManifest::MIME_TYPE,
+                digest_of(config),
+                digest_of(layer),
+                layer.len()
+            )
+        };
+        let (old, new) = (manifest_of(b"old layer"), manifest_of(b"new layer"));
+        block_on(async {
+            for content in [config, b"old layer", b"new layer"] {
+                push_blob(&backend, content).await;
+            }
+            assert_eq!(push_manifest(&backend, "latest", &old).await.status(), 201);
+            assert_eq!(push_manifest(&backend, "latest", &new).await.status(), 201);
+
+            // The tag has moved on, so deleting the manifest it pointed to before leaves it in place.
+            let digest = digest_of(old.as_bytes()).to_string();
+            let req = testing::request("DELETE", "/", RequestBody::empty());
+            let params = [NAME[0], NAME[1], ("reference", digest.as_str())];
+            let res = delete(req, testing::context(&backend, &params)).await.unwrap();
+            assert_eq!(res.status(), 202);
+            let blobs = backend.blobs().unwrap();
+            let tag = storage::find_tag(&blobs, "registry", "team/worker", "latest")
+                .await
+                .unwrap();
+            assert_eq!(tag, Some(digest_of(new.as_bytes()).to_string()));
+
+            // Deleting the tag removes it from the index as well.
+            let req = testing::request("DELETE", "/", RequestBody::empty());
+            let params = [NAME[0], NAME[1], ("reference", "latest")];
+            let res = delete(req, testing::context(&backend, &params)).await.unwrap();
+            assert_eq!(res.status(), 202);
+            assert!(!blobs.keys().iter().any(|key| key.contains("/tagged/")));
+        });
+    }
 }
@@ -3,3 +3,341 @@ pub mod blob;
 pub mod blob_upload;
 pub mod index;
 pub mod manifest;
+pub mod referrer;
+
+use futures_util::{Stream, StreamExt};
+use lazy_static::lazy_static;
+use regex::Regex;
+use std::collections::HashMap;
+use std::future::Future;
+use worker::*;
+
+use crate::auth::Caller;
+use crate::backend::{Backend, BlobStream, WorkerBackend};
+use crate::errors::RegistryError;
+use crate::reference;
+
+/// What the slashes of the image name are replaced with for the router to match it as a single parameter.
+const ESCAPED_SEPARATOR: &str = "%2F";
+
+lazy_static! {
+    static ref NAMED_PATH: Regex =
+        Regex::new(r"^/v2/(.+)/(manifests/[^/]+|blobs/uploads/?[^/]*|blobs/[^/]+|tags/list|referrers/[^/]+)$").unwrap();
+}
+
+/// Split the path of a request about an image into its name, decoded and validated, and the rest of the route.
+fn split_name(path: &str) -> Option<std::result::Result<(String, String), RegistryError>> {
+    let captures = NAMED_PATH.captures(path)?;
+    let name = captures[1].replace(ESCAPED_SEPARATOR, "/").replace("%2f", "/");
+    if let Err(err) = reference::validate_name(&name) {
+        return Some(Err(err));
+    }
+    Some(Ok((name, captures[2].to_string())))
+}
+
+/// Spell the image name of the path the way it is routed, e.g. `/v2/registry%2Fteam/worker/tags/list` as
+/// `/v2/registry/team/worker/tags/list`, so that the access to it is resolved from the same name.
+///
+/// Returns `None` if the path isn't about an image, and `NameInvalid` if its name violates the grammar.
+pub fn normalize_path(path: &str) -> Option<std::result::Result<String, RegistryError>> {
+    Some(split_name(path)?.map(|(name, route)| format!("/v2/{}/{}", name, route)))
+}
+
+/// Escape the slashes of nested image names in the path, e.g. `/v2/registry/team/service/worker/manifests/latest`,
+/// so that the router matches them by `:repository_name/:image_name`.
+///
+/// Returns `None` if the path isn't about an image, and `NameInvalid` if its name violates the grammar.
+pub fn escape_name(path: &str) -> Option<std::result::Result<String, RegistryError>> {
+    Some(split_name(path)?.map(|(name, route)| {
+        let (repository_name, image_name) = name.split_once('/').unwrap();
+        format!(
+            "/v2/{}/{}/{}",
+            repository_name,
+            image_name.replace('/', ESCAPED_SEPARATOR),
+            route
+        )
+    }))
+}
+
+/// Route a request to an image by its name, which may have several components.
+pub fn route_name(req: worker::Request) -> Result<std::result::Result<worker::Request, RegistryError>> {
+    let path = match escape_name(&req.path()) {
+        Some(Ok(path)) => path,
+        Some(Err(err)) => return Ok(Err(err)),
+        None => return Ok(Ok(req)),
+    };
+    if path == req.path() {
+        return Ok(Ok(req));
+    }
+
+    let mut url = req.url()?;
+    url.set_path(&path);
+    let mut init = RequestInit::new();
+    init.with_method(req.method())
+        .with_headers(req.headers().clone())
+        .with_body(req.inner().body().map(Into::into));
+    Ok(Ok(worker::Request::new_with_init(url.as_str(), &init)?))
+}
+
+/// The image name of the route, with the slashes escaped by `route_name`.
+pub fn image_name<S>(ctx: &Context<S>) -> String {
+    ctx.param("image_name").unwrap().replace(ESCAPED_SEPARATOR, "/")
+}
+
+/// Parameters of the routes of the v2 API.
+const PARAMS: [&str; 5] = ["repository_name", "image_name", "reference", "digest", "uuid"];
+
+/// A request to the v2 API, whose URI is the whole URL it has been sent to.
+///
+/// Controllers are detached from the runtime, so that any backend can serve them.
+pub type Request = http::Request<RequestBody>;
+
+/// A response of the v2 API, whose content may be streamed from the blob storage of the backend.
+pub type Response<S> = http::Response<ResponseBody<BlobStream<S>>>;
+
+/// The body of a request, streamed as it's received.
+pub struct RequestBody(Option<Box<dyn Stream<Item = Result<Vec<u8>>> + Unpin>>);
+
+impl RequestBody {
+    pub fn empty() -> Self {
+        Self(None)
+    }
+
+    #[cfg(test)]
+    pub fn from_bytes(bytes: Vec<u8>) -> Self {
+        Self(Some(Box::new(futures_util::stream::iter([Ok(bytes)]))))
+    }
+
+    pub fn from_stream(stream: impl Stream<Item = Result<Vec<u8>>> + Unpin + 'static) -> Self {
+        Self(Some(Box::new(stream)))
+    }
+
+    /// Chunks of the body, if the request has one.
+    pub fn into_stream(self) -> Option<impl Stream<Item = Result<Vec<u8>>> + Unpin> {
+        self.0
+    }
+
+    /// Read the whole body, which is meant for small ones such as manifests.
+    pub async fn bytes(self) -> Result<Vec<u8>> {
+        let mut bytes = Vec::new();
+        if let Some(mut stream) = self.0 {
+            while let Some(chunk) = stream.next().await {
+                bytes.extend_from_slice(&chunk?);
+            }
+        }
+        Ok(bytes)
+    }
+}
+
+pub enum ResponseBody<T> {
+    Empty,
+    Bytes(Vec<u8>),
+    Stream(T),
+}
+
+/// What a controller serves a request with, besides the request itself.
+pub struct Context<S> {
+    /// Who sent the request, as authorized before it was routed.
+    pub caller: Caller,
+
+    pub backend: S,
+
+    params: HashMap<String, String>,
+
+    /// When the request is served, in seconds since the epoch.
+    pub now: u64,
+}
+
+impl<S: Backend> Context<S> {
+    pub fn new(caller: Caller, backend: S, params: HashMap<String, String>, now: u64) -> Self {
+        Self {
+            caller,
+            backend,
+            params,
+            now,
+        }
+    }
+}
+
+impl<S> Context<S> {
+    /// A parameter of the route, e.g. `repository_name`.
+    pub fn param(&self, name: &str) -> Option<&String> {
+        self.params.get(name)
+    }
+}
+
+/// The URL the request has been sent to, e.g. to read its query parameters.
+pub fn url(req: &Request) -> Result<Url> {
+    Ok(Url::parse(&req.uri().to_string())?)
+}
+
+/// The value of a header of the request, if it's valid text.
+pub fn header<'a>(req: &'a Request, name: &str) -> Option<&'a str> {
+    req.headers().get(name).and_then(|value| value.to_str().ok())
+}
+
+/// Finish a response, whose headers are all checked at once.
+pub fn respond<T>(builder: http::response::Builder, body: ResponseBody<T>) -> Result<http::Response<ResponseBody<T>>> {
+    builder.body(body).map_err(|err| Error::RustError(err.to_string()))
+}
+
+/// A response with the value serialized as its JSON body.
+pub fn respond_json<T, V: serde::Serialize>(
+    builder: http::response::Builder,
+    value: &V,
+) -> Result<http::Response<ResponseBody<T>>> {
+    let builder = builder.header("Content-Type", "application/json");
+    respond(builder, ResponseBody::Bytes(serde_json::to_vec(value)?))
+}
+
+/// Serve a request routed by the worker with a controller of the v2 API.
+pub async fn serve<F, T>(mut req: worker::Request, ctx: RouteContext<Caller>, controller: F) -> Result<worker::Response>
+where
+    F: FnOnce(Request, Context<WorkerBackend>) -> T,
+    T: Future<Output = Result<Response<WorkerBackend>>>,
+{
+    let mut builder = http::Request::builder()
+        .method(req.method().as_ref())
+        .uri(req.url()?.as_str());
+    for (name, value) in req.headers().entries() {
+        builder = builder.header(name, value);
+    }
+    let body = match req.inner().body() {
+        Some(_) => RequestBody::from_stream(req.stream()?),
+        None => RequestBody::empty(),
+    };
+    let req = builder.body(body).map_err(|err| Error::RustError(err.to_string()))?;
+
+    let params = PARAMS
+        .iter()
+        .filter_map(|name| ctx.param(name).map(|value| (name.to_string(), value.clone())))
+        .collect();
+    let now = Date::now().as_millis() / 1000;
+    let ctx = Context::new(ctx.data, WorkerBackend::new(ctx.env), params, now);
+
+    let (parts, body) = controller(req, ctx).await?.into_parts();
+    let mut headers = Headers::new();
+    // A header the worker can't carry as text is left out rather than sent without its value.
+    for (name, value) in &parts.headers {
+        if let Ok(value) = value.to_str() {
+            headers.set(name.as_str(), value)?;
+        }
+    }
+    let res = match body {
+        ResponseBody::Empty => worker::Response::empty()?,
+        ResponseBody::Bytes(bytes) => worker::Response::from_bytes(bytes)?,
+        ResponseBody::Stream(stream) => worker::Response::from_body(worker::ResponseBody::Stream(stream))?,
+    };
+    Ok(res.with_status(parts.status.as_u16()).with_headers(headers))
+}
+
+/// Helpers to serve requests to the v2 API from memory in tests.
+#[cfg(test)]
+pub mod testing {
+    use super::*;
+    use crate::auth::access::{self, Access};
+    use crate::backend::memory::MemoryBackend;
+
+    /// A request to the path of the registry.
+    pub fn request(method: &str, path: &str, body: RequestBody) -> Request {
+        http::Request::builder()
+            .method(method)
+            .uri(format!("https://registry.example.com{}", path))
+            .body(body)
+            .unwrap()
+    }
+
+    /// A context with the parameters of the route, sent by a caller allowed to do anything in the repository.
+    ///
+    /// Requests are served at the epoch, as objects of `MemoryBlobStorage` are written by default.
+    pub fn context(backend: &MemoryBackend, params: &[(&str, &str)]) -> Context<MemoryBackend> {
+        let mut caller = Caller {
+            subject: "admin".to_string(),
+            groups: vec![],
+            access: vec![Access::new(access::REGISTRY, "catalog", &["*"])],
+        };
+        if let Some((_, repository_name)) = params.iter().find(|(name, _)| *name == "repository_name") {
+            let name = format!("{}/*", repository_name);
+            caller.access.push(Access::new(access::REPOSITORY, &name, &["*"]));
+        }
+        let params = params
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect();
+        Context::new(caller, backend.clone(), params, 0)
+    }
+
+    /// The whole body of a response.
+    pub fn body(res: Response<MemoryBackend>) -> Vec<u8> {
+        match res.into_body() {
+            ResponseBody::Empty => Vec::new(),
+            ResponseBody::Bytes(bytes) | ResponseBody::Stream(bytes) => bytes,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn escaped(path: &str) -> Option<String> {
+        escape_name(path).map(|escaped| escaped.unwrap())
+    }
+
+    #[test]
+    fn escape_nested_names() {
+        assert_eq!(
+            escaped("/v2/samalba/my-app/manifests/latest").as_deref(),
+            Some("/v2/samalba/my-app/manifests/latest")
+        );
+        assert_eq!(
+            escaped("/v2/registry/team/service/worker/manifests/latest").as_deref(),
+            Some("/v2/registry/team%2Fservice%2Fworker/manifests/latest")
+        );
+        assert_eq!(
+            escaped("/v2/registry/team/worker/blobs/uploads/").as_deref(),
+            Some("/v2/registry/team%2Fworker/blobs/uploads/")
+        );
+        assert_eq!(
+            escaped("/v2/registry/team/blobs/blobs/uploads/uuid").as_deref(),
+            Some("/v2/registry/team%2Fblobs/blobs/uploads/uuid")
+        );
+        assert_eq!(
+            escaped("/v2/registry/team/worker/tags/list").as_deref(),
+            Some("/v2/registry/team%2Fworker/tags/list")
+        );
+        assert_eq!(escaped("/v2/_catalog"), None);
+        assert_eq!(escaped("/v2/"), None);
+    }
+
+    #[test]
+    fn invalid_nested_names() {
+        assert!(matches!(
+            escape_name("/v2/registry/Team/worker/tags/list"),
+            Some(Err(RegistryError::NameInvalid))
+        ));
+        assert!(matches!(
+            escape_name("/v2/registry/manifests/latest"),
+            Some(Err(RegistryError::NameInvalid))
+        ));
+        let long = format!("/v2/registry/{}/manifests/latest", "a".repeat(250));
+        assert!(matches!(escape_name(&long), Some(Err(RegistryError::NameInvalid))));
+    }
+
+    #[test]
+    fn normalize_escaped_names() {
+        assert_eq!(
+            normalize_path("/v2/registry%2Fteam/worker/manifests/latest").and_then(std::result::Result::ok),
+            Some("/v2/registry/team/worker/manifests/latest".to_string())
+        );
+        assert_eq!(
+            normalize_path("/v2/registry/team%2fworker/blobs/uploads/").and_then(std::result::Result::ok),
+            Some("/v2/registry/team/worker/blobs/uploads/".to_string())
+        );
+        assert!(matches!(
+            normalize_path("/v2/registry%2FTeam/worker/tags/list"),
+            Some(Err(RegistryError::NameInvalid))
+        ));
+        assert!(normalize_path("/v2/_catalog").is_none());
+    }
+}
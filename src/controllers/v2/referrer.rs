@@ -0,0 +1,86 @@
+use worker::Result;
+
+use super::{Context, Request, Response};
+use crate::backend::Backend;
+use crate::digest::ContentDigest;
+use crate::media::oci_descriptor::Descriptor;
+use crate::media::oci_image_index::{OciImageIndex, SchemaVersion};
+use crate::storage;
+
+/// List the manifests which refer to the manifest identified by `digest` through their `subject`.
+///
+/// The list is returned as an image index, which is empty if nothing refers to the manifest or it doesn't exist.
+/// Optionally, only the referrers of the `artifactType` query parameter are listed.
+///
+/// See https://github.com/opencontainers/distribution-spec/blob/main/spec.md#listing-referrers
+pub async fn get<S: Backend>(req: Request, ctx: Context<S>) -> Result<Response<S>> {
+    let repository_name = ctx.param("repository_name").unwrap();
+    let image_name = &super::image_name(&ctx);
+    let digest = match ctx.param("digest").unwrap().parse::<ContentDigest>() {
+        Ok(digest) => digest,
+        Err(err) => return err.to_v2_response(),
+    };
+
+    let blobs = ctx.backend.blobs()?;
+    let mut manifests = storage::list_referrers(&blobs, repository_name, image_name, &digest).await?;
+
+    let mut builder = http::Response::builder().header("Content-Type", OciImageIndex::MIME_TYPE);
+
+    let url = super::url(&req)?;
+    if let Some((_, artifact_type)) = url.query_pairs().find(|(key, _)| key == "artifactType") {
+        manifests = filter_artifact_type(manifests, &artifact_type);
+        builder = builder.header("OCI-Filters-Applied", "artifactType");
+    }
+
+    let index = OciImageIndex {
+        schema_version: SchemaVersion::X,
+        media_type: Some(OciImageIndex::MIME_TYPE.to_string()),
+        artifact_type: None,
+        manifests,
+        subject: None,
+        annotations: None,
+    };
+    let body = serde_json::to_vec(&index)?;
+    super::respond(builder, super::ResponseBody::Bytes(body))
+}
+
+fn filter_artifact_type(descriptors: Vec<Descriptor>, artifact_type: &str) -> Vec<Descriptor> {
+    descriptors
+        .into_iter()
+        .filter(|descriptor| descriptor.artifact_type.as_deref() == Some(artifact_type))
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn descriptor(artifact_type: Option<&str>) -> Descriptor {
+        Descriptor {
+            media_type: "application/vnd.oci.image.manifest.v1+json".to_string(),
+            digest: "sha256:5b0bcabd1ed22e9fb1310cf6c2dec7cdef19f0ad69efa1f392e94a4333501270"
+                .parse()
+                .unwrap(),
+            size: 7682,
+            urls: None,
+            annotations: None,
+            artifact_type: artifact_type.map(str::to_string),
+            platform: None,
+        }
+    }
+
+    #[test]
+    fn filter_by_artifact_type() {
+        let descriptors = vec![
+            descriptor(Some("application/vnd.example.sbom.v1")),
+            descriptor(Some("application/vnd.dev.cosign.artifact.sig.v1+json")),
+            descriptor(None),
+        ];
+        let filtered = filter_artifact_type(descriptors, "application/vnd.example.sbom.v1");
+        assert_eq!(filtered.len(), 1);
+        assert_eq!(
+            filtered[0].artifact_type.as_deref(),
+            Some("application/vnd.example.sbom.v1")
+        );
+    }
+}
@@ -1,10 +1,11 @@
 use lazy_static::lazy_static;
 use regex::Regex;
+use serde::{Deserialize, Serialize};
 use std::{fmt, str::FromStr};
 
 use crate::errors::RegistryError;
 
-#[derive(Debug, Eq, PartialEq)]
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
 pub enum SupportedAlgorithm {
     Sha256,
 }
@@ -24,13 +25,14 @@ impl FromStr for SupportedAlgorithm {
         match s {
             "sha256" => Ok(Self::Sha256),
             _ => Err(RegistryError::DigestInvalid {
-                detail: "only sha256 digest currently supported".to_string(),
+                detail: s.to_string(),
+                reason: "only sha256 digest currently supported",
             }),
         }
     }
 }
 
-#[derive(Debug, Eq, PartialEq)]
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
 pub struct ContentDigest {
     pub alg: SupportedAlgorithm,
     pub hash: String,
@@ -61,7 +63,8 @@ impl FromStr for ContentDigest {
                 _ => None,
             })
             .ok_or_else(|| RegistryError::DigestInvalid {
-                detail: "format shoud be `{alg}:{hash}`".to_string(),
+                detail: s.to_string(),
+                reason: "format should be `{alg}:{hash}`",
             })
     }
 }
@@ -90,6 +93,55 @@ mod test {
                 .parse::<ContentDigest>();
         assert!(digest.is_err());
     }
+
+    #[test]
+    fn hash_content() {
+        let hasher = ContentHasher::new();
+        assert_eq!(
+            hasher.digest().to_string(),
+            "sha256:e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+        );
+
+        let mut hasher = ContentHasher::new();
+        hasher.update(b"abc");
+        assert_eq!(
+            hasher.digest().to_string(),
+            "sha256:ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+    }
+
+    #[test]
+    fn hash_content_in_chunks() {
+        use sha2::Digest;
+
+        let content: Vec<u8> = (0..1000u32).map(|n| (n % 251) as u8).collect();
+        let expected = format!("sha256:{:x}", sha2::Sha256::digest(&content));
+
+        for chunk_size in [1, 7, 63, 64, 65, 200] {
+            let mut hasher = ContentHasher::new();
+            for chunk in content.chunks(chunk_size) {
+                hasher.update(chunk);
+            }
+            assert_eq!(hasher.digest().to_string(), expected);
+        }
+    }
+
+    #[test]
+    fn resume_serialized_hasher() {
+        let content = b"The quick brown fox jumps over the lazy dog, again and again and again and again.";
+        let (head, tail) = content.split_at(70);
+
+        let mut hasher = ContentHasher::new();
+        hasher.update(head);
+        let json = serde_json::to_string(&hasher).unwrap();
+
+        let mut hasher: ContentHasher = serde_json::from_str(&json).unwrap();
+        hasher.update(tail);
+
+        let mut expected = ContentHasher::new();
+        expected.update(content);
+        assert_eq!(hasher.digest(), expected.digest());
+    }
 }
 
 impl serde::ser::Serialize for ContentDigest {
@@ -126,3 +178,93 @@ impl<'de> serde::de::Deserialize<'de> for ContentDigest {
         deserializer.deserialize_identifier(Visitor)
     }
 }
+
+/// SHA-256 initial hash value. See FIPS 180-4, 5.3.3
+const SHA256_INITIAL_STATE: [u32; 8] = [
+    0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a, 0x510e527f, 0x9b05688c, 0x1f83d9ab, 0x5be0cd19,
+];
+
+const SHA256_BLOCK_SIZE: usize = 64;
+
+/// Computes the digest of content arriving in chunks.
+///
+/// Unlike hashers of `sha2`, its intermediate state can be serialized, so that hashing of a blob uploaded
+/// through several requests can be resumed by whichever worker receives the next chunk.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ContentHasher {
+    /// The intermediate hash value of all complete blocks so far.
+    state: [u32; 8],
+
+    /// The remaining bytes which don't make up a complete block yet.
+    buffer: Vec<u8>,
+
+    /// The number of bytes hashed so far.
+    length: u64,
+}
+
+impl Default for ContentHasher {
+    fn default() -> Self {
+        Self {
+            state: SHA256_INITIAL_STATE,
+            buffer: Vec::with_capacity(SHA256_BLOCK_SIZE),
+            length: 0,
+        }
+    }
+}
+
+impl ContentHasher {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn update(&mut self, mut data: &[u8]) {
+        self.length += data.len() as u64;
+
+        if !self.buffer.is_empty() {
+            let taken = data.len().min(SHA256_BLOCK_SIZE - self.buffer.len());
+            self.buffer.extend_from_slice(&data[..taken]);
+            data = &data[taken..];
+            if self.buffer.len() < SHA256_BLOCK_SIZE {
+                return;
+            }
+            compress(&mut self.state, &self.buffer);
+            self.buffer.clear();
+        }
+
+        let whole = data.len() - data.len() % SHA256_BLOCK_SIZE;
+        compress(&mut self.state, &data[..whole]);
+        self.buffer.extend_from_slice(&data[whole..]);
+    }
+
+    /// The digest of the content hashed so far. The hasher can still be updated afterwards.
+    pub fn digest(&self) -> ContentDigest {
+        // Pad the message with a single `1` bit, zeros, and the message length in bits. See FIPS 180-4, 5.1.1
+        let mut padding = self.buffer.clone();
+        padding.push(0x80);
+        while padding.len() % SHA256_BLOCK_SIZE != SHA256_BLOCK_SIZE - 8 {
+            padding.push(0);
+        }
+        padding.extend_from_slice(&(self.length * 8).to_be_bytes());
+
+        let mut state = self.state;
+        compress(&mut state, &padding);
+
+        let mut hash = String::with_capacity(64);
+        for word in state {
+            hash.push_str(&format!("{:08x}", word));
+        }
+        ContentDigest {
+            alg: SupportedAlgorithm::Sha256,
+            hash,
+        }
+    }
+}
+
+/// Update the hash value with blocks, the length of which must be a multiple of the block size.
+fn compress(state: &mut [u32; 8], blocks: &[u8]) {
+    let blocks: Vec<_> = blocks
+        .chunks_exact(SHA256_BLOCK_SIZE)
+        .map(|block| <[u8; SHA256_BLOCK_SIZE]>::try_from(block).unwrap().into())
+        .collect();
+    sha2::compress256(state, &blocks);
+}
@@ -0,0 +1,173 @@
+use serde::{Deserialize, Serialize};
+use worker::*;
+
+use crate::storage::EntityStorage;
+
+/// Name of the Durable Object binding of `CatalogObject`. See `wrangler.toml`.
+pub const BINDING: &str = "CATALOG";
+
+/// The single instance which holds the whole catalog.
+pub const CATALOG_NAME: &str = "catalog";
+
+/// A page of `$repository/$image` names in lexical order.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CatalogPage {
+    pub names: Vec<String>,
+
+    /// Whether more names are left after the last one.
+    pub truncated: bool,
+}
+
+/// The names of every image which holds a manifest, in lexical order.
+pub trait CatalogEntity {
+    async fn add(&mut self, repository_name: &str, image_name: &str) -> Result<()>;
+
+    async fn remove(&mut self, repository_name: &str, image_name: &str) -> Result<()>;
+
+    /// List up to `n` names, starting after `last`.
+    async fn list(&self, last: Option<&str>, n: usize) -> Result<CatalogPage>;
+}
+
+/// The catalog in the storage of the single entity which holds it, keyed by `$repository/$image`.
+pub struct CatalogState<S> {
+    storage: S,
+}
+
+impl<S: EntityStorage> CatalogState<S> {
+    pub fn new(storage: S) -> Self {
+        Self { storage }
+    }
+}
+
+impl<S: EntityStorage> CatalogEntity for CatalogState<S> {
+    async fn add(&mut self, repository_name: &str, image_name: &str) -> Result<()> {
+        let name = format!("{}/{}", repository_name, image_name);
+        self.storage.put(&name, &true).await
+    }
+
+    async fn remove(&mut self, repository_name: &str, image_name: &str) -> Result<()> {
+        let name = format!("{}/{}", repository_name, image_name);
+        self.storage.delete(&name).await?;
+        Ok(())
+    }
+
+    async fn list(&self, last: Option<&str>, n: usize) -> Result<CatalogPage> {
+        // Keys are listed from an inclusive start, the smallest key after `last` is `last` followed by NUL.
+        let start = last.map(|last| format!("{}\0", last));
+        let mut names = self.storage.list("", start.as_deref(), Some(n + 1)).await?;
+
+        let truncated = names.len() > n;
+        names.truncate(n);
+        Ok(CatalogPage { names, truncated })
+    }
+}
+
+/// Keeps the names of every image which holds a manifest, keyed by `$repository/$image`.
+///
+/// Names are added when a manifest is pushed and removed when the last manifest of the image is deleted,
+/// so that the catalog can be listed in order without listing the bucket.
+#[durable_object]
+pub struct CatalogObject {
+    state: State,
+}
+
+#[durable_object]
+impl DurableObject for CatalogObject {
+    fn new(state: State, _env: Env) -> Self {
+        Self { state }
+    }
+
+    async fn fetch(&mut self, req: Request) -> Result<Response> {
+        let mut catalog = CatalogState::new(self.state.storage());
+        let url = req.url()?;
+        let param = |name: &str| {
+            url.query_pairs()
+                .find(|(key, _)| key == name)
+                .map(|(_, value)| value.into_owned())
+        };
+
+        match (req.method(), param("name")) {
+            (Method::Put, Some(name)) => {
+                let (repository_name, image_name) = split_name(&name)?;
+                catalog.add(repository_name, image_name).await?;
+                Response::empty()
+            }
+            (Method::Delete, Some(name)) => {
+                let (repository_name, image_name) = split_name(&name)?;
+                catalog.remove(repository_name, image_name).await?;
+                Response::empty()
+            }
+            (Method::Get, _) => {
+                let n = param("n").and_then(|n| n.parse::<usize>().ok()).unwrap_or_default();
+                Response::from_json(&catalog.list(param("last").as_deref(), n).await?)
+            }
+            _ => Response::error("Bad Request", 400),
+        }
+    }
+}
+
+fn split_name(name: &str) -> Result<(&str, &str)> {
+    name.split_once('/')
+        .ok_or_else(|| Error::RustError(format!("{} isn't a `$repository/$image` name", name)))
+}
+
+/// Accesses the `CatalogObject` from a worker.
+pub struct CatalogClient {
+    stub: Stub,
+}
+
+impl CatalogClient {
+    pub fn new(env: &Env) -> Result<Self> {
+        let stub = env.durable_object(BINDING)?.id_from_name(CATALOG_NAME)?.get_stub()?;
+        Ok(Self { stub })
+    }
+
+    async fn send(&self, method: Method, params: &[(&str, &str)]) -> Result<Response> {
+        let mut url = Url::parse("https://catalog/")?;
+        url.query_pairs_mut().extend_pairs(params);
+
+        let mut init = RequestInit::new();
+        init.with_method(method);
+        let req = Request::new_with_init(url.as_str(), &init)?;
+        self.stub.fetch_with_request(req).await
+    }
+}
+
+impl CatalogEntity for CatalogClient {
+    async fn add(&mut self, repository_name: &str, image_name: &str) -> Result<()> {
+        let name = format!("{}/{}", repository_name, image_name);
+        let res = self.send(Method::Put, &[("name", &name)]).await?;
+        match res.status_code() {
+            200 => Ok(()),
+            status => Err(Error::RustError(format!(
+                "failed to add {} to the catalog: {}",
+                name, status
+            ))),
+        }
+    }
+
+    async fn remove(&mut self, repository_name: &str, image_name: &str) -> Result<()> {
+        let name = format!("{}/{}", repository_name, image_name);
+        let res = self.send(Method::Delete, &[("name", &name)]).await?;
+        match res.status_code() {
+            200 => Ok(()),
+            status => Err(Error::RustError(format!(
+                "failed to remove {} from the catalog: {}",
+                name, status
+            ))),
+        }
+    }
+
+    async fn list(&self, last: Option<&str>, n: usize) -> Result<CatalogPage> {
+        let n = n.to_string();
+        let mut params = vec![("n", n.as_str())];
+        if let Some(last) = last {
+            params.push(("last", last));
+        }
+        let mut res = self.send(Method::Get, &params).await?;
+        match res.status_code() {
+            200 => res.json().await,
+            status => Err(Error::RustError(format!("failed to list the catalog: {}", status))),
+        }
+    }
+}
@@ -0,0 +1,3 @@
+pub mod catalog;
+pub mod repository;
+pub mod upload_session;
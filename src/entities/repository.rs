@@ -0,0 +1,558 @@
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use subtle::ConstantTimeEq;
+use worker::wasm_bindgen::JsValue;
+use worker::*;
+
+use crate::auth::policy::{AccessControl, Policy, Visibility};
+use crate::storage::EntityStorage;
+
+/// Name of the Durable Object binding of `RepositoryObject`. See `wrangler.toml`.
+pub const BINDING: &str = "REPOSITORIES";
+
+/// The key of the access policy of the repository.
+const POLICY_KEY: &str = "policy";
+
+/// The key of the visibility of the repository.
+const VISIBILITY_KEY: &str = "visibility";
+
+/// The path of both the visibility and the policy, which are needed together on every request.
+const ACCESS_PATH: &str = "access";
+
+/// Prefix of personal access tokens, which tells them apart from the secrets of robots.
+const TOKEN_PREFIX: &str = "pat_";
+
+/// A secret which robots and personal access tokens log in with. Only its hash is stored.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Secret {
+    /// The hex encoded SHA-256 hash of the secret.
+    #[serde(rename = "secret_hash")]
+    pub hash: String,
+
+    /// When the secret has been issued, in seconds since the epoch.
+    /// Refresh tokens issued before are no longer valid.
+    #[serde(rename = "secret_issued_at")]
+    pub issued_at: u64,
+
+    /// When the secret can no longer be used, in seconds since the epoch.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<u64>,
+
+    /// When the secret has been used to log in for the last time, in seconds since the epoch.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_used_at: Option<u64>,
+}
+
+impl Secret {
+    /// Generate a random secret, returning it along with what is stored of it.
+    pub fn generate(now: u64, expires_at: Option<u64>) -> Result<(String, Self)> {
+        let mut bytes = [0; 32];
+        getrandom::getrandom(&mut bytes).map_err(|err| Error::RustError(err.to_string()))?;
+        let secret = URL_SAFE_NO_PAD.encode(bytes);
+        let stored = Self {
+            hash: Self::hash(&secret),
+            issued_at: now,
+            expires_at,
+            last_used_at: None,
+        };
+        Ok((secret, stored))
+    }
+
+    pub fn hash(secret: &str) -> String {
+        format!("{:x}", Sha256::digest(secret.as_bytes()))
+    }
+
+    /// Compared in constant time, so that the time taken doesn't tell how much of the hash is right.
+    pub fn verify(&self, secret: &str) -> bool {
+        Self::hash(secret).as_bytes().ct_eq(self.hash.as_bytes()).into()
+    }
+
+    pub fn is_expired(&self, now: u64) -> bool {
+        self.expires_at.map_or(false, |expires_at| expires_at <= now)
+    }
+}
+
+/// An entity of a repository which logs in with a secret.
+pub trait Credential: Serialize + DeserializeOwned {
+    /// The collection of the entity, both in the storage and in paths.
+    const KIND: &'static str;
+
+    fn id(&self) -> &str;
+
+    fn secret(&self) -> &Secret;
+
+    fn secret_mut(&mut self) -> &mut Secret;
+
+    /// The password it logs in with, given its secret.
+    fn password(&self, secret: &str) -> String {
+        secret.to_string()
+    }
+}
+
+/// An account for machines, e.g. CI, which pushes and pulls images of a single repository.
+///
+/// Robots log in as `$repository+$name` with their secret.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Robot {
+    pub name: String,
+
+    #[serde(flatten)]
+    pub secret: Secret,
+
+    /// The actions the robot is allowed on every image of the repository.
+    pub actions: Vec<String>,
+
+    pub created_at: u64,
+}
+
+impl Credential for Robot {
+    const KIND: &'static str = "robots";
+
+    fn id(&self) -> &str {
+        &self.name
+    }
+
+    fn secret(&self) -> &Secret {
+        &self.secret
+    }
+
+    fn secret_mut(&mut self) -> &mut Secret {
+        &mut self.secret
+    }
+}
+
+/// A token of a person, who logs in as `$repository+$owner` with `pat_$id_$secret` as the password.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PersonalAccessToken {
+    pub id: String,
+
+    pub owner: String,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+
+    #[serde(flatten)]
+    pub secret: Secret,
+
+    /// The actions the token is allowed on every image of the repository.
+    pub actions: Vec<String>,
+
+    pub created_at: u64,
+}
+
+impl Credential for PersonalAccessToken {
+    const KIND: &'static str = "tokens";
+
+    fn id(&self) -> &str {
+        &self.id
+    }
+
+    fn secret(&self) -> &Secret {
+        &self.secret
+    }
+
+    fn secret_mut(&mut self) -> &mut Secret {
+        &mut self.secret
+    }
+
+    fn password(&self, secret: &str) -> String {
+        format!("{}{}_{}", TOKEN_PREFIX, self.id, secret)
+    }
+}
+
+/// Split the name a robot or a person logs in with into its repository and name.
+pub fn parse_username(username: &str) -> Option<(&str, &str)> {
+    match username.split_once('+') {
+        Some((repository_name, name)) if !repository_name.is_empty() && !name.is_empty() => {
+            Some((repository_name, name))
+        }
+        _ => None,
+    }
+}
+
+/// Split a personal access token into its id and secret, if the password is one.
+pub fn parse_token(password: &str) -> Option<(&str, &str)> {
+    password.strip_prefix(TOKEN_PREFIX)?.split_once('_')
+}
+
+/// A repository, the tenant images are isolated by, identified by its name.
+pub trait RepositoryEntity {
+    /// The visibility and the access policy, which are needed together on every request.
+    async fn access_control(&self) -> Result<AccessControl>;
+
+    async fn visibility(&self) -> Result<Visibility>;
+
+    async fn set_visibility(&mut self, visibility: Visibility) -> Result<()>;
+
+    async fn policy(&self) -> Result<Option<Policy>>;
+
+    async fn put_policy(&mut self, policy: &Policy) -> Result<()>;
+
+    /// Returns `false` if the repository had no policy.
+    async fn delete_policy(&mut self) -> Result<bool>;
+
+    async fn list<T: Credential>(&self) -> Result<Vec<T>>;
+
+    async fn get<T: Credential>(&self, id: &str) -> Result<Option<T>>;
+
+    /// Returns `false` if there is already one with the same id.
+    async fn create<T: Credential>(&mut self, credential: &T) -> Result<bool>;
+
+    async fn delete<T: Credential>(&mut self, id: &str) -> Result<Option<T>>;
+
+    /// Replace the secret, which revokes the previous one.
+    async fn rotate<T: Credential>(&mut self, id: &str, secret: &Secret) -> Result<Option<T>>;
+
+    /// Record that the secret has been used to log in at `now`.
+    async fn touch<T: Credential>(&mut self, id: &str, now: u64) -> Result<()>;
+}
+
+/// The repository in the storage of its entity.
+///
+/// Robots and personal access tokens are stored under `$kind/$id`, along with the visibility and the access policy.
+pub struct RepositoryState<S> {
+    storage: S,
+}
+
+impl<S: EntityStorage> RepositoryState<S> {
+    pub fn new(storage: S) -> Self {
+        Self { storage }
+    }
+
+    /// Update the credential with the given change, returning it as updated.
+    async fn update<T: Credential>(&mut self, id: &str, change: impl FnOnce(&mut T)) -> Result<Option<T>> {
+        let key = credential_key::<T>(id);
+        let mut credential = match self.storage.get::<T>(&key).await? {
+            Some(credential) => credential,
+            None => return Ok(None),
+        };
+        change(&mut credential);
+        self.storage.put(&key, &credential).await?;
+        Ok(Some(credential))
+    }
+}
+
+impl<S: EntityStorage> RepositoryEntity for RepositoryState<S> {
+    async fn access_control(&self) -> Result<AccessControl> {
+        Ok(AccessControl {
+            visibility: self.visibility().await?,
+            policy: self.policy().await?,
+        })
+    }
+
+    async fn visibility(&self) -> Result<Visibility> {
+        Ok(self.storage.get(VISIBILITY_KEY).await?.unwrap_or_default())
+    }
+
+    async fn set_visibility(&mut self, visibility: Visibility) -> Result<()> {
+        self.storage.put(VISIBILITY_KEY, &visibility).await
+    }
+
+    async fn policy(&self) -> Result<Option<Policy>> {
+        self.storage.get(POLICY_KEY).await
+    }
+
+    async fn put_policy(&mut self, policy: &Policy) -> Result<()> {
+        self.storage.put(POLICY_KEY, policy).await
+    }
+
+    async fn delete_policy(&mut self) -> Result<bool> {
+        self.storage.delete(POLICY_KEY).await
+    }
+
+    async fn list<T: Credential>(&self) -> Result<Vec<T>> {
+        let keys = self.storage.list(&format!("{}/", T::KIND), None, None).await?;
+        let mut credentials = Vec::with_capacity(keys.len());
+        for key in keys {
+            credentials.extend(self.storage.get::<T>(&key).await?);
+        }
+        Ok(credentials)
+    }
+
+    async fn get<T: Credential>(&self, id: &str) -> Result<Option<T>> {
+        self.storage.get(&credential_key::<T>(id)).await
+    }
+
+    async fn create<T: Credential>(&mut self, credential: &T) -> Result<bool> {
+        let key = credential_key::<T>(credential.id());
+        if self.storage.get::<T>(&key).await?.is_some() {
+            return Ok(false);
+        }
+        self.storage.put(&key, credential).await?;
+        Ok(true)
+    }
+
+    async fn delete<T: Credential>(&mut self, id: &str) -> Result<Option<T>> {
+        let credential = self.get::<T>(id).await?;
+        if credential.is_some() {
+            self.storage.delete(&credential_key::<T>(id)).await?;
+        }
+        Ok(credential)
+    }
+
+    async fn rotate<T: Credential>(&mut self, id: &str, secret: &Secret) -> Result<Option<T>> {
+        self.update(id, |credential: &mut T| {
+            let last_used_at = credential.secret().last_used_at;
+            *credential.secret_mut() = secret.clone();
+            credential.secret_mut().last_used_at = last_used_at;
+        })
+        .await
+    }
+
+    async fn touch<T: Credential>(&mut self, id: &str, now: u64) -> Result<()> {
+        self.update(id, |credential: &mut T| {
+            credential.secret_mut().last_used_at = Some(now)
+        })
+        .await?;
+        Ok(())
+    }
+}
+
+fn credential_key<T: Credential>(id: &str) -> String {
+    format!("{}/{}", T::KIND, id)
+}
+
+/// Owns the entities of a repository, serving `RepositoryState` to the workers of every request.
+#[durable_object]
+pub struct RepositoryObject {
+    state: State,
+}
+
+#[durable_object]
+impl DurableObject for RepositoryObject {
+    fn new(state: State, _env: Env) -> Self {
+        Self { state }
+    }
+
+    async fn fetch(&mut self, mut req: Request) -> Result<Response> {
+        let mut repository = RepositoryState::new(self.state.storage());
+        let path = req.path();
+        let segments: Vec<&str> = path.trim_matches('/').split('/').collect();
+
+        match (req.method(), segments.as_slice()) {
+            (Method::Get, [ACCESS_PATH]) => Response::from_json(&repository.access_control().await?),
+            (Method::Get, [VISIBILITY_KEY]) => Response::from_json(&repository.visibility().await?),
+            (Method::Put, [VISIBILITY_KEY]) => {
+                let visibility: Visibility = req.json().await?;
+                repository.set_visibility(visibility).await?;
+                Response::from_json(&visibility)
+            }
+            (Method::Get, [POLICY_KEY]) => match repository.policy().await? {
+                Some(policy) => Response::from_json(&policy),
+                None => Response::error("Not Found", 404),
+            },
+            (Method::Put, [POLICY_KEY]) => {
+                let policy: Policy = req.json().await?;
+                repository.put_policy(&policy).await?;
+                Response::from_json(&policy)
+            }
+            (Method::Delete, [POLICY_KEY]) => match repository.delete_policy().await? {
+                true => Response::empty(),
+                false => Response::error("Not Found", 404),
+            },
+            (method, [Robot::KIND, rest @ ..]) => handle::<Robot, _>(&mut repository, method, &mut req, rest).await,
+            (method, [PersonalAccessToken::KIND, rest @ ..]) => {
+                handle::<PersonalAccessToken, _>(&mut repository, method, &mut req, rest).await
+            }
+            _ => Response::error("Not Found", 404),
+        }
+    }
+}
+
+async fn handle<T: Credential, S: EntityStorage>(
+    repository: &mut RepositoryState<S>,
+    method: Method,
+    req: &mut Request,
+    path: &[&str],
+) -> Result<Response> {
+    let credential = match (method, path) {
+        (Method::Get, []) => return Response::from_json(&repository.list::<T>().await?),
+        (Method::Post, []) => {
+            let credential: T = req.json().await?;
+            return match repository.create(&credential).await? {
+                true => Response::from_json(&credential).map(|res| res.with_status(201)),
+                false => Response::error("Conflict", 409),
+            };
+        }
+        (Method::Get, [id]) => repository.get::<T>(id).await?,
+        (Method::Delete, [id]) => repository.delete::<T>(id).await?,
+        (Method::Put, [id, "secret"]) => repository.rotate::<T>(id, &req.json().await?).await?,
+        (Method::Put, [id, "last-used"]) => {
+            repository.touch::<T>(id, req.json().await?).await?;
+            repository.get::<T>(id).await?
+        }
+        _ => return Response::error("Not Found", 404),
+    };
+    match credential {
+        Some(credential) => Response::from_json(&credential),
+        None => Response::error("Not Found", 404),
+    }
+}
+
+/// Accesses the `RepositoryObject` of a repository from a worker.
+pub struct RepositoryClient {
+    stub: Stub,
+}
+
+impl RepositoryClient {
+    pub fn new(env: &Env, repository_name: &str) -> Result<Self> {
+        let stub = env.durable_object(BINDING)?.id_from_name(repository_name)?.get_stub()?;
+        Ok(Self { stub })
+    }
+
+    /// The value the object responded with, or `None` if it wasn't found.
+    async fn parse<T: DeserializeOwned>(mut res: Response) -> Result<Option<T>> {
+        match res.status_code() {
+            200 => Ok(Some(res.json().await?)),
+            404 => Ok(None),
+            status => Err(failed(status)),
+        }
+    }
+
+    /// The value the object responded with, which it always holds.
+    async fn parse_required<T: DeserializeOwned>(res: Response) -> Result<T> {
+        Self::parse(res).await?.ok_or_else(|| failed(404))
+    }
+
+    async fn send(&self, method: Method, path: &str, body: Option<String>) -> Result<Response> {
+        let mut init = RequestInit::new();
+        init.with_method(method);
+        if let Some(body) = body {
+            init.with_body(Some(JsValue::from_str(&body)));
+        }
+        let req = Request::new_with_init(&format!("https://repository{}", path), &init)?;
+        self.stub.fetch_with_request(req).await
+    }
+}
+
+impl RepositoryEntity for RepositoryClient {
+    async fn access_control(&self) -> Result<AccessControl> {
+        let res = self.send(Method::Get, &format!("/{}", ACCESS_PATH), None).await?;
+        Self::parse_required(res).await
+    }
+
+    async fn visibility(&self) -> Result<Visibility> {
+        let res = self.send(Method::Get, &format!("/{}", VISIBILITY_KEY), None).await?;
+        Self::parse_required(res).await
+    }
+
+    async fn set_visibility(&mut self, visibility: Visibility) -> Result<()> {
+        let body = serde_json::to_string(&visibility)?;
+        let res = self
+            .send(Method::Put, &format!("/{}", VISIBILITY_KEY), Some(body))
+            .await?;
+        Self::parse_required::<Visibility>(res).await?;
+        Ok(())
+    }
+
+    async fn policy(&self) -> Result<Option<Policy>> {
+        let res = self.send(Method::Get, &format!("/{}", POLICY_KEY), None).await?;
+        Self::parse(res).await
+    }
+
+    async fn put_policy(&mut self, policy: &Policy) -> Result<()> {
+        let body = serde_json::to_string(policy)?;
+        let res = self.send(Method::Put, &format!("/{}", POLICY_KEY), Some(body)).await?;
+        Self::parse_required::<Policy>(res).await?;
+        Ok(())
+    }
+
+    async fn delete_policy(&mut self) -> Result<bool> {
+        let res = self.send(Method::Delete, &format!("/{}", POLICY_KEY), None).await?;
+        match res.status_code() {
+            200 => Ok(true),
+            404 => Ok(false),
+            status => Err(failed(status)),
+        }
+    }
+
+    async fn list<T: Credential>(&self) -> Result<Vec<T>> {
+        let res = self.send(Method::Get, &format!("/{}", T::KIND), None).await?;
+        Self::parse_required(res).await
+    }
+
+    async fn get<T: Credential>(&self, id: &str) -> Result<Option<T>> {
+        let res = self.send(Method::Get, &format!("/{}/{}", T::KIND, id), None).await?;
+        Self::parse(res).await
+    }
+
+    async fn create<T: Credential>(&mut self, credential: &T) -> Result<bool> {
+        let body = serde_json::to_string(credential)?;
+        let res = self.send(Method::Post, &format!("/{}", T::KIND), Some(body)).await?;
+        match res.status_code() {
+            201 => Ok(true),
+            409 => Ok(false),
+            status => Err(failed(status)),
+        }
+    }
+
+    async fn delete<T: Credential>(&mut self, id: &str) -> Result<Option<T>> {
+        let res = self.send(Method::Delete, &format!("/{}/{}", T::KIND, id), None).await?;
+        Self::parse(res).await
+    }
+
+    async fn rotate<T: Credential>(&mut self, id: &str, secret: &Secret) -> Result<Option<T>> {
+        let body = serde_json::to_string(secret)?;
+        let path = format!("/{}/{}/secret", T::KIND, id);
+        let res = self.send(Method::Put, &path, Some(body)).await?;
+        Self::parse(res).await
+    }
+
+    async fn touch<T: Credential>(&mut self, id: &str, now: u64) -> Result<()> {
+        let path = format!("/{}/{}/last-used", T::KIND, id);
+        let res = self.send(Method::Put, &path, Some(now.to_string())).await?;
+        // The credential may have been revoked since it was verified.
+        Self::parse::<T>(res).await?;
+        Ok(())
+    }
+}
+
+fn failed(status: u16) -> Error {
+    Error::RustError(format!("repository object responded with {}", status))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn verify_secret() {
+        let (secret, stored) = Secret::generate(1_700_000_000, Some(1_800_000_000)).unwrap();
+        assert!(stored.verify(&secret));
+        assert!(!stored.verify("secret"));
+        assert!(!stored.is_expired(1_700_000_000));
+        assert!(stored.is_expired(1_800_000_000));
+
+        let (other, _) = Secret::generate(1_700_000_000, None).unwrap();
+        assert_ne!(secret, other);
+    }
+
+    #[test]
+    fn serialize_robot() {
+        let robot = Robot {
+            name: "ci".to_string(),
+            secret: Secret {
+                hash: Secret::hash("s3cr3t"),
+                issued_at: 1_700_000_000,
+                expires_at: None,
+                last_used_at: None,
+            },
+            actions: vec!["pull".to_string()],
+            created_at: 1_700_000_000,
+        };
+        let json = serde_json::to_value(robot).unwrap();
+        assert_eq!(json["secret_issued_at"], 1_700_000_000);
+        assert!(serde_json::from_value::<Robot>(json).unwrap().secret.verify("s3cr3t"));
+    }
+
+    #[test]
+    fn parse_credentials() {
+        assert_eq!(parse_username("samalba+ci"), Some(("samalba", "ci")));
+        assert_eq!(parse_username("samalba"), None);
+        assert_eq!(parse_username("+ci"), None);
+
+        assert_eq!(parse_token("pat_d3b07384_a_b"), Some(("d3b07384", "a_b")));
+        assert_eq!(parse_token("s3cr3t"), None);
+    }
+}
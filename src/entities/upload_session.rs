@@ -0,0 +1,390 @@
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+use worker::wasm_bindgen::JsValue;
+use worker::*;
+
+use crate::digest::ContentDigest;
+use crate::storage::blob_writer::{self, WriterState};
+use crate::storage::{self, BlobStorage, EntityStorage};
+
+/// Name of the Durable Object binding of `UploadSessionObject`. See `wrangler.toml`.
+pub const BINDING: &str = "UPLOAD_SESSIONS";
+
+const SESSION_KEY: &str = "session";
+
+/// How long an upload is kept without receiving any chunk, in seconds.
+pub const UPLOAD_TIMEOUT: u64 = 24 * 60 * 60;
+
+/// How long a chunk may be written before another one can take over the upload, in seconds.
+pub const LEASE_TIMEOUT: u64 = 15 * 60;
+
+/// State of a resumable blob upload.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UploadSession {
+    pub repository_name: String,
+    pub image_name: String,
+
+    /// The object key the content is uploaded into.
+    pub key: String,
+
+    /// Progress of the content written so far.
+    pub writer: WriterState,
+
+    /// When the upload is aborted unless another chunk is received, in seconds since the epoch.
+    pub expires_at: u64,
+
+    /// The chunk being written, if any.
+    pub lease: Option<Lease>,
+
+    /// The digest of the content once it has been committed under the key, after which no chunk can be written.
+    pub committed: Option<ContentDigest>,
+}
+
+impl UploadSession {
+    /// The number of bytes received so far, i.e. where the next chunk must start.
+    pub fn offset(&self) -> u64 {
+        self.writer.size
+    }
+
+    pub fn is_expired(&self, now: u64) -> bool {
+        self.expires_at <= now
+    }
+}
+
+/// The right to write a chunk into an upload, held by a single request at a time.
+///
+/// Parts and the tail of the upload are written before the session is saved, so two chunks written at once would
+/// write over each other's content while only one of them is counted.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Lease {
+    pub id: String,
+
+    /// When another chunk may take over the upload if this one is never saved, in seconds since the epoch.
+    pub expires_at: u64,
+}
+
+impl Lease {
+    pub fn new(now: u64) -> Self {
+        Self {
+            id: uuid::Uuid::new_v4().to_string(),
+            expires_at: now + LEASE_TIMEOUT,
+        }
+    }
+}
+
+/// A single upload, identified by its uuid.
+pub trait UploadSessionEntity {
+    /// Returns `false` if the upload has already been initiated.
+    async fn create(&mut self, session: &UploadSession) -> Result<bool>;
+
+    async fn load(&self) -> Result<Option<UploadSession>>;
+
+    /// Take the lease of the upload to write the chunk which starts at `offset`.
+    ///
+    /// Returns `false` if the upload has moved past the offset or been committed, or another chunk holds a lease
+    /// which is still valid.
+    async fn acquire(&mut self, offset: u64, lease: &Lease, now: u64) -> Result<bool>;
+
+    /// Save the session written under the lease and release it, unless another chunk has taken it over since.
+    async fn save(&mut self, lease_id: &str, session: &UploadSession) -> Result<bool>;
+
+    /// Release the lease without changing the session, e.g. once the chunk has been rejected.
+    async fn release(&mut self, lease_id: &str) -> Result<()>;
+
+    async fn delete(&mut self) -> Result<()>;
+}
+
+/// The session in the storage of the entity of the upload.
+pub struct UploadSessionState<S> {
+    storage: S,
+}
+
+impl<S: EntityStorage> UploadSessionState<S> {
+    pub fn new(storage: S) -> Self {
+        Self { storage }
+    }
+
+    /// Abort the upload if it has expired, discarding the content written so far along with the session.
+    ///
+    /// See `discard`.
+    ///
+    /// Returns when the upload expires otherwise.
+    pub async fn expire<B: BlobStorage>(&mut self, blobs: &B, now: u64) -> Result<Option<u64>> {
+        let session = match self.load().await? {
+            Some(session) => session,
+            None => return Ok(None),
+        };
+        if !session.is_expired(now) {
+            return Ok(Some(session.expires_at));
+        }
+        discard(blobs, &session).await?;
+        self.delete().await?;
+        Ok(None)
+    }
+}
+
+/// Discard the content written into the upload, unless it has been committed and stored as the content of its digest.
+pub async fn discard<B: BlobStorage>(blobs: &B, session: &UploadSession) -> Result<()> {
+    let digest = match &session.committed {
+        Some(digest) => digest,
+        None => return blob_writer::discard(blobs, &session.key, &session.writer).await,
+    };
+    let content = blobs.head(&storage::content_key(digest)).await?;
+    let is_stored = content.map_or(false, |content| {
+        content.metadata.custom.get(storage::LINK_METADATA) == Some(&session.key)
+    });
+    if !is_stored {
+        blobs.delete(&session.key).await?;
+    }
+    Ok(())
+}
+
+impl<S: EntityStorage> UploadSessionEntity for UploadSessionState<S> {
+    async fn create(&mut self, session: &UploadSession) -> Result<bool> {
+        if self.load().await?.is_some() {
+            return Ok(false);
+        }
+        self.storage.put(SESSION_KEY, session).await?;
+        Ok(true)
+    }
+
+    async fn load(&self) -> Result<Option<UploadSession>> {
+        self.storage.get(SESSION_KEY).await
+    }
+
+    async fn acquire(&mut self, offset: u64, lease: &Lease, now: u64) -> Result<bool> {
+        match self.load().await? {
+            Some(mut current)
+                if current.offset() == offset
+                    && current.committed.is_none()
+                    && current.lease.as_ref().map_or(true, |held| held.expires_at <= now) =>
+            {
+                current.lease = Some(lease.clone());
+                self.storage.put(SESSION_KEY, &current).await?;
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
+    }
+
+    async fn save(&mut self, lease_id: &str, session: &UploadSession) -> Result<bool> {
+        match self.load().await? {
+            Some(current) if holds(&current, lease_id) => {
+                let session = UploadSession {
+                    lease: None,
+                    ..session.clone()
+                };
+                self.storage.put(SESSION_KEY, &session).await?;
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
+    }
+
+    async fn release(&mut self, lease_id: &str) -> Result<()> {
+        match self.load().await? {
+            Some(mut current) if holds(&current, lease_id) => {
+                current.lease = None;
+                self.storage.put(SESSION_KEY, &current).await
+            }
+            _ => Ok(()),
+        }
+    }
+
+    async fn delete(&mut self) -> Result<()> {
+        self.storage.delete_all().await
+    }
+}
+
+fn holds(session: &UploadSession, lease_id: &str) -> bool {
+    session.lease.as_ref().map_or(false, |lease| lease.id == lease_id)
+}
+
+/// Owns the state of a single upload, identified by its uuid.
+///
+/// The object only stores the session. The content itself is streamed into R2 by the worker
+/// which received the chunk while holding the lease of the upload, then the new state is saved along with releasing it.
+///
+/// An alarm is set to the deadline of the session, which aborts the upload if no chunk has pushed it back.
+#[durable_object]
+pub struct UploadSessionObject {
+    state: State,
+    env: Env,
+}
+
+impl UploadSessionObject {
+    /// Set the alarm to when the session expires, in seconds since the epoch.
+    async fn set_deadline(&self, expires_at: u64) -> Result<()> {
+        let now = Date::now().as_millis() / 1000;
+        let offset = Duration::from_secs(expires_at.saturating_sub(now));
+        self.state.storage().set_alarm(offset).await
+    }
+}
+
+#[durable_object]
+impl DurableObject for UploadSessionObject {
+    fn new(state: State, env: Env) -> Self {
+        Self { state, env }
+    }
+
+    async fn fetch(&mut self, mut req: Request) -> Result<Response> {
+        let mut session = UploadSessionState::new(self.state.storage());
+        let url = req.url()?;
+        let query = |name: &str| {
+            url.query_pairs()
+                .find(|(key, _)| key == name)
+                .map(|(_, value)| value.into_owned())
+        };
+        let lease_id = req.path().strip_prefix("/lease/").map(str::to_string);
+        match (req.method(), lease_id) {
+            (Method::Post, Some(id)) => {
+                let offset = query("offset").and_then(|offset| offset.parse::<u64>().ok());
+                let expires_at = query("expires_at").and_then(|expires_at| expires_at.parse::<u64>().ok());
+                let now = query("now").and_then(|now| now.parse::<u64>().ok());
+                let acquired = match (offset, expires_at, now) {
+                    (Some(offset), Some(expires_at), Some(now)) => {
+                        session.acquire(offset, &Lease { id, expires_at }, now).await?
+                    }
+                    _ => return Response::error("Bad Request", 400),
+                };
+                match acquired {
+                    true => Response::empty(),
+                    false => Response::error("Conflict", 409),
+                }
+            }
+            (Method::Delete, Some(id)) => {
+                session.release(&id).await?;
+                Response::empty()
+            }
+            (_, Some(_)) => Response::error("Method Not Allowed", 405),
+            (Method::Get, None) => match session.load().await? {
+                Some(current) => Response::from_json(&current),
+                None => Response::error("Not Found", 404),
+            },
+            (Method::Post, None) => {
+                let created: UploadSession = req.json().await?;
+                if !session.create(&created).await? {
+                    return Response::error("Conflict", 409);
+                }
+                self.set_deadline(created.expires_at).await?;
+                Response::empty()
+            }
+            (Method::Put, None) => {
+                let updated: UploadSession = req.json().await?;
+                let saved = match query("lease") {
+                    Some(lease_id) => session.save(&lease_id, &updated).await?,
+                    None => false,
+                };
+                if !saved {
+                    return Response::error("Conflict", 409);
+                }
+                self.set_deadline(updated.expires_at).await?;
+                Response::empty()
+            }
+            (Method::Delete, None) => {
+                self.state.storage().delete_alarm().await?;
+                session.delete().await?;
+                Response::empty()
+            }
+            _ => Response::error("Method Not Allowed", 405),
+        }
+    }
+
+    async fn alarm(&mut self) -> Result<Response> {
+        let blobs = self.env.bucket(storage::BUCKET_BINDING)?;
+        let now = Date::now().as_millis() / 1000;
+        let mut session = UploadSessionState::new(self.state.storage());
+        if let Some(expires_at) = session.expire(&blobs, now).await? {
+            self.set_deadline(expires_at).await?;
+        }
+        Response::empty()
+    }
+}
+
+/// Accesses the `UploadSessionObject` of an upload from a worker.
+pub struct UploadSessionClient {
+    stub: Stub,
+}
+
+impl UploadSessionClient {
+    pub fn new(env: &Env, uuid: &str) -> Result<Self> {
+        let stub = env.durable_object(BINDING)?.id_from_name(uuid)?.get_stub()?;
+        Ok(Self { stub })
+    }
+
+    async fn send(&self, method: Method, path: &str, session: Option<&UploadSession>) -> Result<Response> {
+        let mut init = RequestInit::new();
+        init.with_method(method);
+        if let Some(session) = session {
+            init.with_body(Some(JsValue::from_str(&serde_json::to_string(session)?)));
+        }
+        let req = Request::new_with_init(&format!("https://upload-session{}", path), &init)?;
+        self.stub.fetch_with_request(req).await
+    }
+}
+
+impl UploadSessionEntity for UploadSessionClient {
+    async fn create(&mut self, session: &UploadSession) -> Result<bool> {
+        let res = self.send(Method::Post, "/", Some(session)).await?;
+        match res.status_code() {
+            200 => Ok(true),
+            409 => Ok(false),
+            status => Err(Error::RustError(format!("failed to create upload session: {}", status))),
+        }
+    }
+
+    async fn load(&self) -> Result<Option<UploadSession>> {
+        let mut res = self.send(Method::Get, "/", None).await?;
+        match res.status_code() {
+            200 => Ok(Some(res.json().await?)),
+            404 => Ok(None),
+            status => Err(Error::RustError(format!("failed to load upload session: {}", status))),
+        }
+    }
+
+    async fn acquire(&mut self, offset: u64, lease: &Lease, now: u64) -> Result<bool> {
+        let path = format!(
+            "/lease/{}?offset={}&expires_at={}&now={}",
+            lease.id, offset, lease.expires_at, now
+        );
+        let res = self.send(Method::Post, &path, None).await?;
+        match res.status_code() {
+            200 => Ok(true),
+            409 => Ok(false),
+            status => Err(Error::RustError(format!(
+                "failed to acquire upload session: {}",
+                status
+            ))),
+        }
+    }
+
+    async fn save(&mut self, lease_id: &str, session: &UploadSession) -> Result<bool> {
+        let res = self
+            .send(Method::Put, &format!("/?lease={}", lease_id), Some(session))
+            .await?;
+        match res.status_code() {
+            200 => Ok(true),
+            409 => Ok(false),
+            status => Err(Error::RustError(format!("failed to save upload session: {}", status))),
+        }
+    }
+
+    async fn release(&mut self, lease_id: &str) -> Result<()> {
+        let res = self.send(Method::Delete, &format!("/lease/{}", lease_id), None).await?;
+        match res.status_code() {
+            200 => Ok(()),
+            status => Err(Error::RustError(format!(
+                "failed to release upload session: {}",
+                status
+            ))),
+        }
+    }
+
+    async fn delete(&mut self) -> Result<()> {
+        let res = self.send(Method::Delete, "/", None).await?;
+        match res.status_code() {
+            200 => Ok(()),
+            status => Err(Error::RustError(format!("failed to delete upload session: {}", status))),
+        }
+    }
+}
@@ -1,4 +1,12 @@
+use serde::Serialize;
+use serde_json::{json, Value};
 use std::fmt;
+use worker::{Headers, Response};
+
+use crate::controllers::v2;
+
+/// Why a digest is invalid when it doesn't match the content it has been provided for.
+pub const DIGEST_MISMATCH: &str = "provided digest did not match uploaded content";
 
 // See https://docs.docker.com/registry/spec/api/#errors-2
 #[derive(Debug)]
@@ -20,7 +28,9 @@ pub enum RegistryError {
     /// the digest provided by the client. The error may include a detail structure
     /// with the key “digest”, including the invalid digest string. This error may
     /// also be returned when a manifest includes an invalid layer digest.
-    DigestInvalid { detail: String },
+    ///
+    /// The detail is the digest as provided, and the reason explains what's wrong with it.
+    DigestInvalid { detail: String, reason: &'static str },
 
     /// This error may be returned when a manifest blob is unknown to the registry.
     ManifestBlobUnknown,
@@ -78,7 +88,8 @@ impl fmt::Display for RegistryError {
             Self::BlobUnknown => write!(f, "blob unknown to registry"),
             Self::BlobUploadInvalid => write!(f, "blob upload is invalid"),
             Self::BlobUploadUnknown => write!(f, "blob upload is unknown to registry"),
-            Self::DigestInvalid { detail } => write!(f, "provided digest did not match uploaded content: {}", detail),
+            Self::DigestInvalid { detail, reason } if detail.is_empty() => write!(f, "{}", reason),
+            Self::DigestInvalid { detail, reason } => write!(f, "{}: {}", reason, detail),
             Self::ManifestBlobUnknown => write!(f, "manifest blob is unknown to registry"),
             Self::ManifestInvalid { detail } => write!(f, "manifest is invalid: {}", detail),
             Self::ManifestUnknown => write!(f, "manifest unknown"),
@@ -102,3 +113,162 @@ impl fmt::Display for RegistryError {
         }
     }
 }
+
+impl RegistryError {
+    /// The error code defined by the spec, which clients match against.
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::BlobUnknown => "BLOB_UNKNOWN",
+            Self::BlobUploadInvalid => "BLOB_UPLOAD_INVALID",
+            Self::BlobUploadUnknown => "BLOB_UPLOAD_UNKNOWN",
+            Self::DigestInvalid { .. } => "DIGEST_INVALID",
+            Self::ManifestBlobUnknown => "MANIFEST_BLOB_UNKNOWN",
+            Self::ManifestInvalid { .. } => "MANIFEST_INVALID",
+            Self::ManifestUnknown => "MANIFEST_UNKNOWN",
+            Self::ManifestUnverified => "MANIFEST_UNVERIFIED",
+            Self::NameInvalid => "NAME_INVALID",
+            Self::NameUnknown => "NAME_UNKNOWN",
+            Self::PaginationNumberInvalid => "PAGINATION_NUMBER_INVALID",
+            Self::RangeInvalid => "RANGE_INVALID",
+            Self::SizeInvalid { .. } => "SIZE_INVALID",
+            Self::TagInvalid => "TAG_INVALID",
+            Self::Unauthorized => "UNAUTHORIZED",
+            Self::Denied => "DENIED",
+            Self::Unsupported => "UNSUPPORTED",
+        }
+    }
+
+    /// The HTTP status code the error is returned with.
+    ///
+    /// Follows the reference implementation where the spec leaves it open.
+    /// See https://github.com/distribution/distribution/blob/main/registry/api/v2/errors.go
+    pub fn status(&self) -> u16 {
+        match self {
+            Self::BlobUnknown | Self::BlobUploadUnknown => 404,
+            Self::ManifestUnknown | Self::NameUnknown => 404,
+            Self::BlobUploadInvalid | Self::DigestInvalid { .. } | Self::SizeInvalid { .. } => 400,
+            Self::ManifestBlobUnknown | Self::ManifestInvalid { .. } | Self::ManifestUnverified => 400,
+            Self::NameInvalid | Self::TagInvalid | Self::PaginationNumberInvalid => 400,
+            Self::RangeInvalid => 416,
+            Self::Unauthorized => 401,
+            Self::Denied => 403,
+            Self::Unsupported => 405,
+        }
+    }
+
+    /// Additional information about the error, if any.
+    pub fn detail(&self) -> Option<Value> {
+        match self {
+            Self::DigestInvalid { detail, .. } if !detail.is_empty() => Some(json!({ "digest": detail })),
+            Self::ManifestInvalid { detail } => Some(Value::String(detail.clone())),
+            Self::SizeInvalid {
+                uploaded_size,
+                expected_size,
+            } => Some(json!({
+                "uploaded": uploaded_size,
+                "expected": expected_size,
+            })),
+            _ => None,
+        }
+    }
+
+    /// The error serialized into the body of the spec.
+    ///
+    /// See https://docs.docker.com/registry/spec/api/#errors
+    pub fn to_body(&self) -> ErrorBody {
+        ErrorBody {
+            errors: vec![ErrorInfo {
+                code: self.code(),
+                message: self.to_string(),
+                detail: self.detail(),
+            }],
+        }
+    }
+
+    pub fn to_response(&self) -> worker::Result<Response> {
+        let mut headers = Headers::new();
+        headers.set("Content-Type", "application/json; charset=utf-8")?;
+        let body = serde_json::to_vec(&self.to_body())?;
+        Ok(Response::from_bytes(body)?
+            .with_status(self.status())
+            .with_headers(headers))
+    }
+
+    /// The error as a response of the v2 API, whichever backend serves it.
+    pub fn to_v2_response<T>(&self) -> worker::Result<http::Response<v2::ResponseBody<T>>> {
+        let builder = http::Response::builder()
+            .status(self.status())
+            .header("Content-Type", "application/json; charset=utf-8");
+        v2::respond(builder, v2::ResponseBody::Bytes(serde_json::to_vec(&self.to_body())?))
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct ErrorBody {
+    pub errors: Vec<ErrorInfo>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ErrorInfo {
+    pub code: &'static str,
+    pub message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub detail: Option<Value>,
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn serialize_error_body() {
+        let err = RegistryError::DigestInvalid {
+            detail: "sha256:6c3c624b58dbbcd3c0dd82b4c53f04194d1247c6eebdaab7c610cf7d66709b3b".to_string(),
+            reason: DIGEST_MISMATCH,
+        };
+        assert_eq!(err.status(), 400);
+        assert_eq!(
+            serde_json::to_value(err.to_body()).unwrap(),
+            json!({
+                "errors": [{
+                    "code": "DIGEST_INVALID",
+                    "message": "provided digest did not match uploaded content: sha256:6c3c624b58dbbcd3c0dd82b4c53f04194d1247c6eebdaab7c610cf7d66709b3b",
+                    "detail": { "digest": "sha256:6c3c624b58dbbcd3c0dd82b4c53f04194d1247c6eebdaab7c610cf7d66709b3b" },
+                }],
+            })
+        );
+    }
+
+    #[test]
+    fn explain_invalid_digest() {
+        let err = "sha256:not-hex".parse::<crate::digest::ContentDigest>().unwrap_err();
+        assert_eq!(err.to_string(), "format should be `{alg}:{hash}`: sha256:not-hex");
+        assert_eq!(err.detail(), Some(json!({ "digest": "sha256:not-hex" })));
+    }
+
+    #[test]
+    fn serialize_error_body_without_detail() {
+        let err = RegistryError::BlobUnknown;
+        assert_eq!(err.status(), 404);
+        assert_eq!(RegistryError::BlobUploadInvalid.status(), 400);
+        assert_eq!(
+            serde_json::to_value(err.to_body()).unwrap(),
+            json!({
+                "errors": [{
+                    "code": "BLOB_UNKNOWN",
+                    "message": "blob unknown to registry",
+                }],
+            })
+        );
+    }
+
+    #[test]
+    fn serialize_size_detail() {
+        let err = RegistryError::SizeInvalid {
+            uploaded_size: 1024,
+            expected_size: 2048,
+        };
+        assert_eq!(err.code(), "SIZE_INVALID");
+        assert_eq!(err.detail(), Some(json!({ "uploaded": 1024, "expected": 2048 })));
+    }
+}
@@ -0,0 +1,696 @@
+//! Mark-and-sweep collection of what no tag leads to anymore, run by the `scheduled` event.
+//!
+//! A cycle goes through every image of the catalog, marking the manifests and blobs reachable from its tags
+//! and sweeping the rest, then through the content of every blob, which is deleted once no image links it.
+//! A single run can't finish a cycle within the CPU limits of a worker, so each one goes through a bounded number
+//! of keys or contents and saves where it left off for the next one, even in the middle of an image.
+
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use worker::{Env, Error, Result};
+
+use crate::backend::Backend;
+use crate::digest::ContentDigest;
+use crate::entities::catalog::CatalogEntity;
+use crate::media::Manifest;
+use crate::storage::{self, BlobStorage, Metadata, ObjectInfo};
+
+/// Object key of the progress of the current cycle.
+const STATE_KEY: &str = "_gc/state";
+
+/// Object key of the report of the last finished cycle.
+const REPORT_KEY: &str = "_gc/report";
+
+/// The number of tags, manifests and blob links of images gone through by a single run.
+///
+/// Starting an image counts as a key as well, so that a run over many small images stays bounded.
+const KEYS_PER_RUN: usize = 100;
+
+/// The number of blob contents swept by a single run.
+const CONTENTS_PER_RUN: usize = 100;
+
+/// How long anything is kept after it has been written unless configured otherwise, in seconds.
+const DEFAULT_GRACE_PERIOD: u64 = 24 * 60 * 60;
+
+/// Configuration of garbage collection, from the variables of `wrangler.toml`.
+pub struct GcConfig {
+    /// How long anything is kept after it has been written, even if nothing leads to it, in seconds.
+    ///
+    /// Blobs are pushed before the manifest which refers to them, and must not be collected in between.
+    pub grace_period: u64,
+
+    /// Only report what would be freed, without deleting anything.
+    pub dry_run: bool,
+}
+
+impl GcConfig {
+    pub fn from_env(env: &Env) -> Result<Self> {
+        let grace_period = match env.var("GC_GRACE_PERIOD") {
+            Ok(grace_period) => grace_period
+                .to_string()
+                .parse()
+                .map_err(|_| Error::RustError("GC_GRACE_PERIOD must be a number of seconds".to_string()))?,
+            Err(_) => DEFAULT_GRACE_PERIOD,
+        };
+        let dry_run = env
+            .var("GC_DRY_RUN")
+            .map_or(false, |dry_run| dry_run.to_string() == "true");
+        Ok(Self { grace_period, dry_run })
+    }
+}
+
+/// What a cycle has freed, or would have in a dry run.
+///
+/// Links aren't removed in a dry run, so contents are only reported once no image links them at all.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GcReport {
+    /// When the cycle started, in seconds since the epoch.
+    pub started_at: u64,
+
+    pub dry_run: bool,
+
+    /// The number of manifests deleted.
+    pub manifests: u64,
+
+    /// The number of blobs unlinked from images.
+    pub links: u64,
+
+    /// The number of blob contents deleted.
+    pub contents: u64,
+
+    /// The size of the deleted manifests and contents in bytes.
+    pub bytes: u64,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum Phase {
+    #[default]
+    Images,
+    Contents,
+}
+
+/// Progress of a cycle, saved between runs.
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct GcState {
+    phase: Phase,
+
+    /// The last image name or content key done in the phase.
+    cursor: Option<String>,
+
+    /// The image left in the middle of being marked and swept, which comes right after the cursor.
+    #[serde(default)]
+    image: Option<ImageState>,
+
+    report: GcReport,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum Step {
+    /// Reading the tags, whose manifests are reachable.
+    #[default]
+    Tags,
+
+    /// Following the reachable manifests to the manifests, blobs and referrers they lead to.
+    Marks,
+
+    /// Deleting the manifests which aren't reachable.
+    Manifests,
+
+    /// Unlinking the blobs which aren't reachable.
+    Blobs,
+}
+
+/// Progress of marking and sweeping an image, saved between runs along with what has been marked so far.
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct ImageState {
+    /// The `$repository/$image` name of the image.
+    name: String,
+
+    step: Step,
+
+    /// The last tag, manifest or blob link key done in the step.
+    cursor: Option<String>,
+
+    /// Digests of reachable manifests which haven't been followed yet.
+    pending: Vec<ContentDigest>,
+
+    manifests: HashSet<ContentDigest>,
+
+    blobs: HashSet<ContentDigest>,
+}
+
+/// Run the collection for a while, continuing the cycle where the previous run left off.
+///
+/// Returns the report of the cycle once it's finished, the next run starts another one.
+pub async fn collect<S: Backend>(backend: &S, config: &GcConfig, now: u64) -> Result<Option<GcReport>> {
+    let blobs = backend.blobs()?;
+    let mut state = match blobs.get(STATE_KEY).await? {
+        Some((_, state)) => serde_json::from_slice(&state)?,
+        None => GcState {
+            report: GcReport {
+                started_at: now,
+                dry_run: config.dry_run,
+                ..Default::default()
+            },
+            ..Default::default()
+        },
+    };
+    let collector = Collector {
+        blobs: &blobs,
+        config,
+        now,
+    };
+
+    match state.phase {
+        Phase::Images => {
+            let mut catalog = backend.catalog()?;
+            let mut budget = KEYS_PER_RUN;
+            while budget > 0 {
+                let mut image = match state.image.take() {
+                    Some(image) => image,
+                    None => match catalog.list(state.cursor.as_deref(), 1).await?.names.pop() {
+                        Some(name) => {
+                            budget -= 1;
+                            ImageState {
+                                name,
+                                ..Default::default()
+                            }
+                        }
+                        None => {
+                            state.phase = Phase::Contents;
+                            state.cursor = None;
+                            break;
+                        }
+                    },
+                };
+                if collector
+                    .sweep_image(&mut catalog, &mut image, &mut budget, &mut state.report)
+                    .await?
+                {
+                    state.cursor = Some(image.name);
+                } else {
+                    state.image = Some(image);
+                }
+            }
+        }
+        Phase::Contents => {
+            let (keys, truncated) = blobs
+                .list(storage::CONTENTS_PREFIX, state.cursor.as_deref(), CONTENTS_PER_RUN)
+                .await?;
+            for key in keys {
+                collector.sweep_content(&key, &mut state.report).await?;
+                state.cursor = Some(key);
+            }
+            if !truncated {
+                let report = serde_json::to_vec(&state.report)?;
+                blobs.put(REPORT_KEY, report, Metadata::default()).await?;
+                blobs.delete(STATE_KEY).await?;
+                return Ok(Some(state.report));
+            }
+        }
+    }
+
+    let state = serde_json::to_vec(&state)?;
+    blobs.put(STATE_KEY, state, Metadata::default()).await?;
+    Ok(None)
+}
+
+struct Collector<'a, B> {
+    blobs: &'a B,
+    config: &'a GcConfig,
+    now: u64,
+}
+
+impl<'a, B: BlobStorage> Collector<'a, B> {
+    /// Whether the object has been written long enough ago to be collected.
+    fn is_expired(&self, object: &ObjectInfo) -> bool {
+        object.uploaded + self.config.grace_period <= self.now
+    }
+
+    /// Mark and sweep the image from where it has been left, going through no more keys than the budget.
+    ///
+    /// Manifests and blobs which its tags don't lead to are deleted and unlinked respectively, and referrers are
+    /// kept along with their subject, e.g. the signatures of a tagged image. Returns whether the image is done.
+    async fn sweep_image(
+        &self,
+        catalog: &mut impl CatalogEntity,
+        image: &mut ImageState,
+        budget: &mut usize,
+        report: &mut GcReport,
+    ) -> Result<bool> {
+        let name = image.name.clone();
+        let (repository_name, image_name) = match name.split_once('/') {
+            Some(name) => name,
+            None => return Ok(true),
+        };
+        while *budget > 0 {
+            let prefix = match image.step {
+                Step::Tags => storage::tags_prefix(repository_name, image_name),
+                Step::Marks => {
+                    self.mark(repository_name, image_name, image, budget).await?;
+                    continue;
+                }
+                Step::Manifests => storage::manifests_prefix(repository_name, image_name),
+                Step::Blobs => storage::blobs_prefix(repository_name, image_name),
+            };
+            let (keys, truncated) = self.blobs.list(&prefix, image.cursor.as_deref(), *budget).await?;
+            for key in keys {
+                match image.step {
+                    Step::Tags => {
+                        if let Some((_, body)) = self.blobs.get(&key).await? {
+                            if let Ok(digest) = String::from_utf8_lossy(&body).parse::<ContentDigest>() {
+                                image.pending.push(digest);
+                            }
+                        }
+                    }
+                    Step::Manifests => {
+                        if let Ok(digest) = key[prefix.len()..].parse::<ContentDigest>() {
+                            if !image.manifests.contains(&digest) {
+                                self.sweep_manifest(repository_name, image_name, &key, &digest, report)
+                                    .await?;
+                            }
+                        }
+                    }
+                    _ => {
+                        if let Ok(digest) = key[prefix.len()..].parse::<ContentDigest>() {
+                            if !image.blobs.contains(&digest) {
+                                self.sweep_link(repository_name, image_name, &key, &digest, report)
+                                    .await?;
+                            }
+                        }
+                    }
+                }
+                image.cursor = Some(key);
+                *budget -= 1;
+            }
+            if truncated {
+                continue;
+            }
+
+            image.cursor = None;
+            image.step = match image.step {
+                Step::Tags => Step::Marks,
+                Step::Manifests => {
+                    // The image leaves the catalog along with its last manifest, the same way as when it's deleted.
+                    if !self.config.dry_run && self.blobs.list(&prefix, None, 1).await?.0.is_empty() {
+                        catalog.remove(repository_name, image_name).await?;
+                    }
+                    Step::Blobs
+                }
+                _ => return Ok(true),
+            };
+        }
+        Ok(false)
+    }
+
+    /// Follow the reachable manifests of the image to what they lead to, a manifest per key of the budget.
+    async fn mark(
+        &self,
+        repository_name: &str,
+        image_name: &str,
+        image: &mut ImageState,
+        budget: &mut usize,
+    ) -> Result<()> {
+        while *budget > 0 {
+            let digest = match image.pending.pop() {
+                Some(digest) => digest,
+                None => {
+                    image.step = Step::Manifests;
+                    return Ok(());
+                }
+            };
+            if image.manifests.contains(&digest) {
+                continue;
+            }
+            let manifest = storage::find_manifest(self.blobs, repository_name, image_name, &digest).await?;
+            if let Some((media_type, body)) = manifest {
+                if let Ok(manifest) = Manifest::parse(&media_type, &String::from_utf8_lossy(&body)) {
+                    image.blobs.extend(manifest.blobs().into_iter().cloned());
+                    image.pending.extend(manifest.manifests().into_iter().cloned());
+                }
+            }
+            let referrers = storage::list_referrers(self.blobs, repository_name, image_name, &digest).await?;
+            image
+                .pending
+                .extend(referrers.into_iter().map(|referrer| referrer.digest));
+            image.manifests.insert(digest);
+            *budget -= 1;
+        }
+        Ok(())
+    }
+
+    /// Delete a manifest which isn't reachable, along with its referrer record.
+    async fn sweep_manifest(
+        &self,
+        repository_name: &str,
+        image_name: &str,
+        key: &str,
+        digest: &ContentDigest,
+        report: &mut GcReport,
+    ) -> Result<()> {
+        let (object, body) = match self.blobs.get(key).await? {
+            Some((object, body)) if self.is_expired(&object) => (object, body),
+            _ => return Ok(()),
+        };
+        report.manifests += 1;
+        report.bytes += object.size;
+        if self.config.dry_run {
+            return Ok(());
+        }
+
+        let media_type = object.metadata.content_type.unwrap_or_default();
+        if let Ok(manifest) = Manifest::parse(&media_type, &String::from_utf8_lossy(&body)) {
+            if let Some(subject) = manifest.subject() {
+                let key = storage::referrer_key(repository_name, image_name, &subject.digest, digest);
+                self.blobs.delete(&key).await?;
+            }
+        }
+        self.blobs.delete(key).await
+    }
+
+    /// Unlink a blob which isn't reachable.
+    async fn sweep_link(
+        &self,
+        repository_name: &str,
+        image_name: &str,
+        key: &str,
+        digest: &ContentDigest,
+        report: &mut GcReport,
+    ) -> Result<()> {
+        match self.blobs.head(key).await? {
+            Some(link) if self.is_expired(&link) => {}
+            _ => return Ok(()),
+        }
+        report.links += 1;
+        if !self.config.dry_run {
+            storage::unlink_blob(self.blobs, repository_name, image_name, digest).await?;
+        }
+        Ok(())
+    }
+
+    /// Delete the content of a blob if no image links it anymore.
+    ///
+    /// Images without any manifest have left the catalog, so their links are swept here instead.
+    async fn sweep_content(&self, key: &str, report: &mut GcReport) -> Result<()> {
+        let digest = match key[storage::CONTENTS_PREFIX.len()..].parse::<ContentDigest>() {
+            Ok(digest) => digest,
+            Err(_) => return Ok(()),
+        };
+
+        let mut is_referenced = false;
+        for name in storage::list_references(self.blobs, &digest).await? {
+            let (repository_name, image_name) = name.split_once('/').unwrap_or_default();
+            let link_key = storage::blob_key(repository_name, image_name, &digest);
+            let link = match self.blobs.head(&link_key).await? {
+                Some(link) => link,
+                None => {
+                    // The reference of a link which has never been put, e.g. by a push which failed in between.
+                    let reference_key = storage::reference_key(&digest, repository_name, image_name);
+                    match self.blobs.head(&reference_key).await? {
+                        Some(reference) if self.is_expired(&reference) => {
+                            if !self.config.dry_run {
+                                self.blobs.delete(&reference_key).await?;
+                            }
+                        }
+                        _ => is_referenced = true,
+                    }
+                    continue;
+                }
+            };
+
+            let manifests_prefix = storage::manifests_prefix(repository_name, image_name);
+            let has_manifests = !self.blobs.list(&manifests_prefix, None, 1).await?.0.is_empty();
+            if has_manifests || !self.is_expired(&link) {
+                is_referenced = true;
+                continue;
+            }
+            report.links += 1;
+            if !self.config.dry_run {
+                storage::unlink_blob(self.blobs, repository_name, image_name, &digest).await?;
+            }
+        }
+        if is_referenced {
+            return Ok(());
+        }
+
+        let content = match self.blobs.head(key).await? {
+            Some(content) if self.is_expired(&content) => content,
+            _ => return Ok(()),
+        };
+        // Content written by a resumable upload stays where it was uploaded.
+        let upload_key = content.metadata.custom.get(storage::LINK_METADATA);
+        let size = match upload_key {
+            Some(upload_key) => self.blobs.head(upload_key).await?.map_or(0, |upload| upload.size),
+            None => content.size,
+        };
+        report.contents += 1;
+        report.bytes += size;
+        if self.config.dry_run {
+            return Ok(());
+        }
+
+        self.blobs.delete(key).await?;
+        if let Some(upload_key) = upload_key {
+            self.blobs.delete(upload_key).await?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::backend::memory::MemoryBackend;
+    use crate::controllers::v2::{blob_upload, manifest, testing, RequestBody};
+    use crate::digest::ContentHasher;
+    use crate::media::oci_image_manifest::OciImageManifest;
+    use futures_executor::block_on;
+
+    const NAME: [(&str, &str); 2] = [("repository_name", "registry"), ("image_name", "team%2Fworker")];
+
+    const GRACE_PERIOD: u64 = 3600;
+
+    fn digest_of(content: &[u8]) -> ContentDigest {
+        let mut hasher = ContentHasher::new();
+        hasher.update(content);
+        hasher.digest()
+    }
+
+    async fn push_blob(backend: &MemoryBackend, content: &[u8]) {
+        let path = format!("/v2/registry/team/worker/blobs/uploads/?digest={}", digest_of(content));
+        let req = testing::request("POST", &path, RequestBody::from_bytes(content.to_vec()));
+        let res = blob_upload::initiate(req, testing::context(backend, &NAME))
+            .await
+            .unwrap();
+        assert_eq!(res.status(), 201);
+    }
+
+    /// Push an image of the layer under the tag, returning the digest of its manifest.
+    async fn push_image(backend: &MemoryBackend, tag: &str, layer: &[u8]) -> ContentDigest {
+        let config = b"{}".as_slice();
+        push_blob(backend, config).await;
+        push_blob(backend, layer).await;
+
+        let manifest = format!(
+            r#"{{"schemaVersion":2,"mediaType":"{}","config":{{"mediaType":"application/vnd.oci.image.config.v1+json","digest":"{}","size":{}}},"layers":[{{"mediaType":"application/vnd.oci.image.layer.v1.tar","digest":"{}","size":{}}}]}}"#,
+            OciImageManifest::MIME_TYPE,
+            digest_of(config),
+            config.len(),
+            digest_of(layer),
+            layer.len()
+        );
+        let path = format!("/v2/registry/team/worker/manifests/{}", tag);
+        let mut req = testing::request("PUT", &path, RequestBody::from_bytes(manifest.as_bytes().to_vec()));
+        req.headers_mut()
+            .insert("Content-Type", OciImageManifest::MIME_TYPE.parse().unwrap());
+        let params = [NAME[0], NAME[1], ("reference", tag)];
+        let res = manifest::put(req, testing::context(backend, &params)).await.unwrap();
+        assert_eq!(res.status(), 201);
+        digest_of(manifest.as_bytes())
+    }
+
+    /// Run the collection until its cycle is finished.
+    async fn collect_cycle(backend: &MemoryBackend, config: &GcConfig, now: u64) -> GcReport {
+        loop {
+            if let Some(report) = collect(backend, config, now).await.unwrap() {
+                return report;
+            }
+        }
+    }
+
+    #[test]
+    fn collect_untagged_images() {
+        let backend = MemoryBackend::default();
+        let blobs = backend.blobs().unwrap();
+        let config = GcConfig {
+            grace_period: GRACE_PERIOD,
+            dry_run: false,
+        };
+        let (old_layer, new_layer) = (b"old layer".as_slice(), b"new layer".as_slice());
+        block_on(async {
+            let old = push_image(&backend, "latest", old_layer).await;
+            let new = push_image(&backend, "latest", new_layer).await;
+
+            // Nothing is collected within the grace period.
+            let report = collect_cycle(&backend, &config, GRACE_PERIOD - 1).await;
+            assert_eq!((report.manifests, report.links, report.contents), (0, 0, 0));
+            assert!(blobs
+                .head(&storage::manifest_key("registry", "team/worker", &old))
+                .await
+                .unwrap()
+                .is_some());
+
+            let report = collect_cycle(&backend, &config, GRACE_PERIOD).await;
+            let manifest_size = blobs
+                .head(&storage::manifest_key("registry", "team/worker", &new))
+                .await
+                .unwrap()
+                .unwrap()
+                .size;
+            assert_eq!(
+                report,
+                GcReport {
+                    started_at: GRACE_PERIOD,
+                    dry_run: false,
+                    manifests: 1,
+                    links: 1,
+                    contents: 1,
+                    // Both manifests only differ by their layer, which has the same length.
+                    bytes: manifest_size + old_layer.len() as u64,
+                }
+            );
+            assert!(blobs
+                .head(&storage::manifest_key("registry", "team/worker", &old))
+                .await
+                .unwrap()
+                .is_none());
+            assert!(!storage::has_content(&blobs, &digest_of(old_layer)).await.unwrap());
+
+            // The tagged image is left whole.
+            for content in [b"{}".as_slice(), new_layer] {
+                let blob = storage::find_blob(&blobs, "registry", "team/worker", &digest_of(content)).await;
+                assert!(blob.unwrap().is_some());
+            }
+            assert_eq!(
+                blobs.get(REPORT_KEY).await.unwrap().map(|(_, report)| report),
+                Some(serde_json::to_vec(&report).unwrap())
+            );
+            assert!(blobs.head(STATE_KEY).await.unwrap().is_none());
+        });
+    }
+
+    #[test]
+    fn collect_deleted_images() {
+        let backend = MemoryBackend::default();
+        let blobs = backend.blobs().unwrap();
+        let config = GcConfig {
+            grace_period: GRACE_PERIOD,
+            dry_run: false,
+        };
+        block_on(async {
+            let digest = push_image(&backend, "latest", b"layer").await;
+            let path = format!("/v2/registry/team/worker/manifests/{}", digest);
+            let req = testing::request("DELETE", &path, RequestBody::empty());
+            let params = [NAME[0], NAME[1], ("reference", &digest.to_string())];
+            let res = manifest::delete(req, testing::context(&backend, &params))
+                .await
+                .unwrap();
+            assert_eq!(res.status(), 202);
+
+            // A blob pushed ahead of its manifest is kept for the grace period.
+            blobs.set_now(GRACE_PERIOD);
+            push_blob(&backend, b"pending layer").await;
+
+            // The image has left the catalog, its blobs are found from their contents.
+            let report = collect_cycle(&backend, &config, GRACE_PERIOD).await;
+            assert_eq!((report.manifests, report.links, report.contents), (0, 2, 2));
+            let digest = digest_of(b"pending layer");
+            let blob_keys = blobs
+                .keys()
+                .into_iter()
+                .filter(|key| key.starts_with(storage::CONTENTS_PREFIX) || key.contains("/blobs/"))
+                .collect::<Vec<_>>();
+            assert_eq!(
+                blob_keys,
+                vec![
+                    storage::content_key(&digest),
+                    storage::reference_key(&digest, "registry", "team/worker"),
+                    storage::blob_key("registry", "team/worker", &digest),
+                ]
+            );
+        });
+    }
+
+    #[test]
+    fn collect_image_over_several_runs() {
+        let backend = MemoryBackend::default();
+        let blobs = backend.blobs().unwrap();
+        let config = GcConfig {
+            grace_period: GRACE_PERIOD,
+            dry_run: false,
+        };
+        block_on(async {
+            let old = push_image(&backend, "old", b"old layer").await;
+            let req = testing::request("DELETE", "/v2/registry/team/worker/manifests/old", RequestBody::empty());
+            let params = [NAME[0], NAME[1], ("reference", "old")];
+            let res = manifest::delete(req, testing::context(&backend, &params))
+                .await
+                .unwrap();
+            assert_eq!(res.status(), 202);
+            // More tags than a run goes through, e.g. by CI builds.
+            let mut new = None;
+            for n in 0..KEYS_PER_RUN {
+                new = Some(push_image(&backend, &format!("ci-{}", n), b"new layer").await);
+            }
+
+            // The run stops in the middle of the image, which the next one carries on with.
+            assert!(collect(&backend, &config, GRACE_PERIOD).await.unwrap().is_none());
+            let (_, state) = blobs.get(STATE_KEY).await.unwrap().unwrap();
+            let state: GcState = serde_json::from_slice(&state).unwrap();
+            let image = state.image.unwrap();
+            assert_eq!((image.name.as_str(), image.step), ("registry/team/worker", Step::Tags));
+            assert!(image.cursor.is_some());
+
+            let report = collect_cycle(&backend, &config, GRACE_PERIOD).await;
+            assert_eq!((report.manifests, report.links, report.contents), (1, 1, 1));
+            assert!(storage::find_manifest(&blobs, "registry", "team/worker", &old)
+                .await
+                .unwrap()
+                .is_none());
+            assert!(storage::find_manifest(&blobs, "registry", "team/worker", &new.unwrap())
+                .await
+                .unwrap()
+                .is_some());
+        });
+    }
+
+    #[test]
+    fn dry_run() {
+        let backend = MemoryBackend::default();
+        let blobs = backend.blobs().unwrap();
+        let config = GcConfig {
+            grace_period: GRACE_PERIOD,
+            dry_run: true,
+        };
+        block_on(async {
+            push_image(&backend, "latest", b"old layer").await;
+            push_image(&backend, "latest", b"new layer").await;
+            let keys = blobs.keys();
+
+            let report = collect_cycle(&backend, &config, GRACE_PERIOD).await;
+            assert!(report.dry_run);
+            // The old layer is still linked, so its content isn't reported yet.
+            assert_eq!((report.manifests, report.links, report.contents), (1, 1, 0));
+            assert_eq!(
+                blobs
+                    .keys()
+                    .into_iter()
+                    .filter(|key| key != REPORT_KEY)
+                    .collect::<Vec<_>>(),
+                keys
+            );
+        });
+    }
+}
@@ -1,80 +1,186 @@
+mod auth;
+mod backend;
 mod controllers;
 mod digest;
+mod entities;
 mod errors;
+mod gc;
 mod media;
+mod reference;
+mod storage;
 mod utils;
 
+use entities::repository::{PersonalAccessToken, Robot};
 use worker::*;
 
 #[event(fetch)]
 pub async fn main(req: Request, env: Env, _ctx: worker::Context) -> Result<Response> {
     utils::set_panic_hook();
 
-    console_log!(
-        "\n{} {}\n{:#?}Body: {:?}\n",
-        req.method().to_string(),
-        req.path(),
-        req.headers(),
-        req.inner().body().map(|b| b.to_string()),
-    );
+    // Headers and bodies carry credentials, e.g. `Authorization` and the body of `/token`, so they're never logged.
+    console_log!("{} {}", req.method().to_string(), req.path());
 
     // Image tag convention: `[hostname]/[repository_name]/[image_name]`
     // - the hostname is where the worker deployed.
     // - the "repository" is a logically isolated unit of an image repository.
     // - the "image" is an identifier.
 
+    let config = auth::AuthConfig::from_env(&env, &req.url()?)?;
+    let now = Date::now().as_millis() / 1000;
+    // The access is resolved from the name the request is routed by, however the client spelled it.
+    let path = match controllers::v2::normalize_path(&req.path()) {
+        Some(Ok(path)) => path,
+        Some(Err(err)) => return err.to_response(),
+        None => req.path(),
+    };
+    let requirement = auth::requirement(&req.method(), &path);
+    let caller = match auth::authorize_request(&req, &requirement, &config, &env, now).await? {
+        Ok(caller) => caller,
+        Err(rejection) => return rejection.to_response(&config),
+    };
+
+    // Images may have nested names, e.g. `registry/team/service/worker`.
+    let req = match controllers::v2::route_name(req)? {
+        Ok(req) => req,
+        Err(err) => return err.to_response(),
+    };
+
     // See https://docs.docker.com/registry/spec/api/#detail
-    Router::new()
-        // index
-        .get_async("/v2/", controllers::v2::index::get_base)
+    Router::with_data(caller)
+        // token
+        .get_async("/token", controllers::token::get)
+        .post_async("/token", controllers::token::post)
+        // management
         .get_async(
-            "/v2/:repository_name/:image_name/tags/list",
-            controllers::v2::index::get_tags,
+            "/_admin/v1/repositories/:repository_name/robots",
+            controllers::admin::list::<Robot>,
         )
-        .get_async("/v2/_catalog", controllers::v2::index::get_catalog)
-        // manifests
-        .get_async(
-            "/v2/:repository_name/:image_name/manifests/:reference",
-            controllers::v2::manifest::get,
+        .post_async(
+            "/_admin/v1/repositories/:repository_name/robots",
+            controllers::admin::create_robot,
         )
-        .put_async(
-            "/v2/:repository_name/:image_name/manifests/:reference",
-            controllers::v2::manifest::put,
+        .get_async(
+            "/_admin/v1/repositories/:repository_name/robots/:id",
+            controllers::admin::get::<Robot>,
         )
         .delete_async(
-            "/v2/:repository_name/:image_name/manifests/:reference",
-            controllers::v2::manifest::delete,
+            "/_admin/v1/repositories/:repository_name/robots/:id",
+            controllers::admin::revoke::<Robot>,
+        )
+        .post_async(
+            "/_admin/v1/repositories/:repository_name/robots/:id/rotate",
+            controllers::admin::rotate::<Robot>,
         )
-        // blobs
         .get_async(
-            "/v2/:repository_name/:image_name/blobs/:digest",
-            controllers::v2::blob::get,
+            "/_admin/v1/repositories/:repository_name/tokens",
+            controllers::admin::list::<PersonalAccessToken>,
+        )
+        .post_async(
+            "/_admin/v1/repositories/:repository_name/tokens",
+            controllers::admin::create_token,
+        )
+        .get_async(
+            "/_admin/v1/repositories/:repository_name/tokens/:id",
+            controllers::admin::get::<PersonalAccessToken>,
         )
         .delete_async(
-            "/v2/:repository_name/:image_name/blobs/:digest",
-            controllers::v2::blob::delete,
+            "/_admin/v1/repositories/:repository_name/tokens/:id",
+            controllers::admin::revoke::<PersonalAccessToken>,
         )
-        // uploads
         .post_async(
-            "/v2/:repository_name/:image_name/blobs/uploads",
-            controllers::v2::blob_upload::initiate,
+            "/_admin/v1/repositories/:repository_name/tokens/:id/rotate",
+            controllers::admin::rotate::<PersonalAccessToken>,
         )
         .get_async(
-            "/v2/:repository_name/:image_name/blobs/uploads/:uuid",
-            controllers::v2::blob_upload::get,
+            "/_admin/v1/repositories/:repository_name/visibility",
+            controllers::admin::get_visibility,
         )
-        .patch_async(
-            "/v2/:repository_name/:image_name/blobs/uploads/:uuid",
-            controllers::v2::blob_upload::append_chunk,
+        .put_async(
+            "/_admin/v1/repositories/:repository_name/visibility",
+            controllers::admin::put_visibility,
+        )
+        .get_async(
+            "/_admin/v1/repositories/:repository_name/policy",
+            controllers::admin::get_policy,
         )
         .put_async(
-            "/v2/:repository_name/:image_name/blobs/uploads/:uuid",
-            controllers::v2::blob_upload::complete,
+            "/_admin/v1/repositories/:repository_name/policy",
+            controllers::admin::put_policy,
         )
         .delete_async(
-            "/v2/:repository_name/:image_name/blobs/uploads/:uuid",
-            controllers::v2::blob_upload::delete,
+            "/_admin/v1/repositories/:repository_name/policy",
+            controllers::admin::delete_policy,
         )
+        // index
+        .get_async("/v2/", |req, ctx| {
+            controllers::v2::serve(req, ctx, controllers::v2::index::get_base)
+        })
+        .get_async("/v2/:repository_name/:image_name/tags/list", |req, ctx| {
+            controllers::v2::serve(req, ctx, controllers::v2::index::get_tags)
+        })
+        .get_async("/v2/_catalog", |req, ctx| {
+            controllers::v2::serve(req, ctx, controllers::v2::index::get_catalog)
+        })
+        // manifests
+        .get_async("/v2/:repository_name/:image_name/manifests/:reference", |req, ctx| {
+            controllers::v2::serve(req, ctx, controllers::v2::manifest::get)
+        })
+        .head_async("/v2/:repository_name/:image_name/manifests/:reference", |req, ctx| {
+            controllers::v2::serve(req, ctx, controllers::v2::manifest::get)
+        })
+        .put_async("/v2/:repository_name/:image_name/manifests/:reference", |req, ctx| {
+            controllers::v2::serve(req, ctx, controllers::v2::manifest::put)
+        })
+        .delete_async("/v2/:repository_name/:image_name/manifests/:reference", |req, ctx| {
+            controllers::v2::serve(req, ctx, controllers::v2::manifest::delete)
+        })
+        // referrers
+        .get_async("/v2/:repository_name/:image_name/referrers/:digest", |req, ctx| {
+            controllers::v2::serve(req, ctx, controllers::v2::referrer::get)
+        })
+        // blobs
+        .get_async("/v2/:repository_name/:image_name/blobs/:digest", |req, ctx| {
+            controllers::v2::serve(req, ctx, controllers::v2::blob::get)
+        })
+        .head_async("/v2/:repository_name/:image_name/blobs/:digest", |req, ctx| {
+            controllers::v2::serve(req, ctx, controllers::v2::blob::get)
+        })
+        .delete_async("/v2/:repository_name/:image_name/blobs/:digest", |req, ctx| {
+            controllers::v2::serve(req, ctx, controllers::v2::blob::delete)
+        })
+        // uploads
+        .post_async("/v2/:repository_name/:image_name/blobs/uploads", |req, ctx| {
+            controllers::v2::serve(req, ctx, controllers::v2::blob_upload::initiate)
+        })
+        .get_async("/v2/:repository_name/:image_name/blobs/uploads/:uuid", |req, ctx| {
+            controllers::v2::serve(req, ctx, controllers::v2::blob_upload::get)
+        })
+        .patch_async("/v2/:repository_name/:image_name/blobs/uploads/:uuid", |req, ctx| {
+            controllers::v2::serve(req, ctx, controllers::v2::blob_upload::append_chunk)
+        })
+        .put_async("/v2/:repository_name/:image_name/blobs/uploads/:uuid", |req, ctx| {
+            controllers::v2::serve(req, ctx, controllers::v2::blob_upload::complete)
+        })
+        .delete_async("/v2/:repository_name/:image_name/blobs/uploads/:uuid", |req, ctx| {
+            controllers::v2::serve(req, ctx, controllers::v2::blob_upload::delete)
+        })
         .run(req, env)
         .await
 }
+
+/// Collect what no tag leads to anymore, a batch per run. See `gc`.
+#[event(scheduled)]
+pub async fn collect_garbage(_event: ScheduledEvent, env: Env, _ctx: ScheduleContext) {
+    utils::set_panic_hook();
+
+    let now = Date::now().as_millis() / 1000;
+    let result = match gc::GcConfig::from_env(&env) {
+        Ok(config) => gc::collect(&backend::WorkerBackend::new(env), &config, now).await,
+        Err(err) => Err(err),
+    };
+    match result {
+        Ok(Some(report)) => console_log!("garbage collection finished: {:?}", report),
+        Ok(None) => {}
+        Err(err) => console_error!("garbage collection failed: {}", err),
+    }
+}
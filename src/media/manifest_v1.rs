@@ -37,6 +37,9 @@ pub struct ManifestV1 {
 
 impl ManifestV1 {
     pub const MIME_TYPE: &'static str = "application/vnd.docker.distribution.manifest.v1+json";
+
+    /// Schema1 manifests are pushed along with their JWS signatures.
+    pub const SIGNED_MIME_TYPE: &'static str = "application/vnd.docker.distribution.manifest.v1+prettyjws";
 }
 
 pub enum ManifestV1Error {
@@ -32,8 +32,38 @@ pub struct ManifestV2 {
     pub layers: Vec<ImageLayer>,
 }
 
+impl ManifestV2 {
+    pub const MIME_TYPE: &'static str = "application/vnd.docker.distribution.manifest.v2+json";
+
+    /// Check the descriptors which can't be expressed by the schema alone.
+    pub fn validate(&self) -> Result<(), ManifestV2Error> {
+        if self.config.size == 0 {
+            return Err(ManifestV2Error::InvalidConfig(
+                "config size must be positive".to_string(),
+            ));
+        }
+        for layer in &self.layers {
+            if !ImageLayer::MEDIA_TYPES.contains(&layer.media_type.as_str()) {
+                let detail = format!("unknown layer media type: {}", layer.media_type);
+                return Err(ManifestV2Error::InvalidLayer(detail));
+            }
+            if layer.size == 0 {
+                let detail = format!("size of layer {} must be positive", layer.digest);
+                return Err(ManifestV2Error::InvalidLayer(detail));
+            }
+            if layer.digest == self.config.digest {
+                let detail = format!("layer {} duplicates the config digest", layer.digest);
+                return Err(ManifestV2Error::InvalidLayer(detail));
+            }
+        }
+        Ok(())
+    }
+}
+
 pub enum ManifestV2Error {
     ParsingError(serde_json::Error),
+    InvalidConfig(String),
+    InvalidLayer(String),
 }
 
 impl From<serde_json::Error> for ManifestV2Error {
@@ -48,6 +78,9 @@ impl From<ManifestV2Error> for RegistryError {
             ManifestV2Error::ParsingError(err) => Self::ManifestInvalid {
                 detail: err.to_string(),
             },
+            ManifestV2Error::InvalidConfig(detail) | ManifestV2Error::InvalidLayer(detail) => {
+                Self::ManifestInvalid { detail }
+            }
         }
     }
 }
@@ -57,6 +90,7 @@ impl FromStr for ManifestV2 {
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         let manifest: Self = serde_json::from_str(s)?;
+        manifest.validate()?;
         Ok(manifest)
     }
 }
@@ -88,6 +122,33 @@ pub struct ImageLayer {
     /// This field exists so that a client will have an expected size for the content before validating.
     /// If the length of the retrieved content does not match the specified length, the content should not be trusted.
     pub size: u64,
+
+    /// The digest of the content, as defined by the [Registry V2 HTTP API Specificiation](https://docs.docker.com/registry/spec/api/#digest-parameter).
+    pub digest: digest::ContentDigest,
+
+    /// Provides a list of URLs from which the content may be fetched.
+    /// Content should be verified against the digest and size. This field is optional and uncommon.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub urls: Option<Vec<String>>,
+}
+
+impl ImageLayer {
+    pub const MIME_TYPE: &'static str = "application/vnd.docker.image.rootfs.diff.tar.gzip";
+    pub const FOREIGN_MIME_TYPE: &'static str = "application/vnd.docker.image.rootfs.foreign.diff.tar.gzip";
+
+    /// Media types of layers an image manifest may reference, including those of OCI layers.
+    pub const MEDIA_TYPES: [&'static str; 5] = [
+        Self::MIME_TYPE,
+        Self::FOREIGN_MIME_TYPE,
+        "application/vnd.oci.image.layer.v1.tar",
+        "application/vnd.oci.image.layer.v1.tar+gzip",
+        "application/vnd.oci.image.layer.v1.tar+zstd",
+    ];
+
+    /// Whether the layer is pulled from its `urls` instead of being pushed to the registry.
+    pub fn is_foreign(&self) -> bool {
+        self.media_type == Self::FOREIGN_MIME_TYPE
+    }
 }
 
 #[cfg(test)]
@@ -99,5 +160,64 @@ mod test {
         let json = include_str!("../../tests/data/manifest_v2.json");
         let manifest = json.parse::<ManifestV2>();
         assert!(manifest.is_ok());
+
+        let manifest = manifest.ok().unwrap();
+        assert_eq!(manifest.layers.len(), 3);
+        assert_eq!(
+            manifest.layers[0].digest.to_string(),
+            "sha256:e692418e4cbaf90ca69d05a66403747baa33ee08806650b51fab815ad7fc331f"
+        );
+        assert!(manifest.layers[0].urls.is_none());
+    }
+
+    #[test]
+    fn parse_foreign_layer() {
+        let json = include_str!("../../tests/data/manifest_v2.json").replacen(
+            r#""mediaType": "application/vnd.docker.image.rootfs.diff.tar.gzip","#,
+            r#""mediaType": "application/vnd.docker.image.rootfs.foreign.diff.tar.gzip", "urls": ["https://example.com/layer"],"#,
+            1,
+        );
+        let manifest = json.parse::<ManifestV2>().ok().unwrap();
+        assert!(manifest.layers[0].is_foreign());
+        assert_eq!(
+            manifest.layers[0].urls,
+            Some(vec!["https://example.com/layer".to_string()])
+        );
+    }
+
+    fn validate(json: &str) -> Result<ManifestV2, RegistryError> {
+        Ok(json.parse::<ManifestV2>()?)
+    }
+
+    #[test]
+    fn invalid_layer_media_type() {
+        let json = include_str!("../../tests/data/manifest_v2.json").replacen(
+            "application/vnd.docker.image.rootfs.diff.tar.gzip",
+            "text/plain",
+            1,
+        );
+        assert!(matches!(validate(&json), Err(RegistryError::ManifestInvalid { .. })));
+    }
+
+    #[test]
+    fn invalid_layer_size() {
+        let json = include_str!("../../tests/data/manifest_v2.json");
+        let zero = json.replacen("32654", "0", 1);
+        assert!(matches!(validate(&zero), Err(RegistryError::ManifestInvalid { .. })));
+        let negative = json.replacen("32654", "-1", 1);
+        assert!(matches!(
+            validate(&negative),
+            Err(RegistryError::ManifestInvalid { .. })
+        ));
+    }
+
+    #[test]
+    fn invalid_duplicate_config_digest() {
+        let json = include_str!("../../tests/data/manifest_v2.json").replacen(
+            "e692418e4cbaf90ca69d05a66403747baa33ee08806650b51fab815ad7fc331f",
+            "b5b2b2c507a0944348e0303114d8d93aaaa081732b86451d9bce1f432a537bc7",
+            1,
+        );
+        assert!(matches!(validate(&json), Err(RegistryError::ManifestInvalid { .. })));
     }
 }
@@ -1,3 +1,216 @@
 pub mod manifest_list;
 pub mod manifest_v1;
 pub mod manifest_v2;
+pub mod oci_descriptor;
+pub mod oci_image_index;
+pub mod oci_image_manifest;
+
+use std::collections::HashMap;
+use std::str::FromStr;
+
+use crate::digest::ContentDigest;
+use crate::errors::RegistryError;
+use manifest_list::ManifestList;
+use manifest_v1::ManifestV1;
+use manifest_v2::ManifestV2;
+use oci_descriptor::Descriptor;
+use oci_image_index::OciImageIndex;
+use oci_image_manifest::OciImageManifest;
+
+/// A manifest of any of the supported media types.
+#[derive(Debug)]
+pub enum Manifest {
+    V1(ManifestV1),
+    V2(ManifestV2),
+    List(ManifestList),
+    OciImage(Box<OciImageManifest>),
+    OciIndex(Box<OciImageIndex>),
+}
+
+impl Manifest {
+    /// Parse the manifest according to its media type, i.e. the `Content-Type` it was pushed with.
+    pub fn parse(media_type: &str, s: &str) -> Result<Self, RegistryError> {
+        // Parameters such as `charset` don't affect parsing.
+        let media_type = media_type.split(';').next().unwrap_or_default().trim();
+        match media_type {
+            ManifestV2::MIME_TYPE => Ok(Self::V2(ManifestV2::from_str(s)?)),
+            ManifestList::MIME_TYPE => Ok(Self::List(ManifestList::from_str(s)?)),
+            OciImageManifest::MIME_TYPE => Ok(Self::OciImage(Box::new(OciImageManifest::from_str(s)?))),
+            OciImageIndex::MIME_TYPE => Ok(Self::OciIndex(Box::new(OciImageIndex::from_str(s)?))),
+            ManifestV1::MIME_TYPE | ManifestV1::SIGNED_MIME_TYPE => Ok(Self::V1(ManifestV1::from_str(s)?)),
+            _ => Err(RegistryError::ManifestInvalid {
+                detail: format!("unsupported media type: {}", media_type),
+            }),
+        }
+    }
+
+    /// Digests of the blobs the manifest references, i.e. the config and layers of an image.
+    ///
+    /// Foreign and non-distributable layers are left out, they are never pushed to the registry.
+    pub fn blobs(&self) -> Vec<&ContentDigest> {
+        match self {
+            Self::V1(manifest) => manifest.fs_layers.iter().map(|layer| &layer.blob_sum).collect(),
+            Self::V2(manifest) => std::iter::once(&manifest.config.digest)
+                .chain(
+                    manifest
+                        .layers
+                        .iter()
+                        .filter(|layer| !layer.is_foreign())
+                        .map(|layer| &layer.digest),
+                )
+                .collect(),
+            Self::OciImage(manifest) => std::iter::once(&manifest.config.digest)
+                .chain(
+                    manifest
+                        .layers
+                        .iter()
+                        .filter(|layer| !layer.media_type.contains(".nondistributable."))
+                        .map(|layer| &layer.digest),
+                )
+                .collect(),
+            Self::List(_) | Self::OciIndex(_) => vec![],
+        }
+    }
+
+    /// Digests of the other manifests the manifest references, i.e. the images of a manifest list.
+    pub fn manifests(&self) -> Vec<&ContentDigest> {
+        match self {
+            Self::List(manifest_list) => manifest_list.manifests.iter().map(|item| &item.digest).collect(),
+            Self::OciIndex(index) => index.manifests.iter().map(|descriptor| &descriptor.digest).collect(),
+            _ => vec![],
+        }
+    }
+
+    /// The manifest this one refers to, e.g. the image a signature or an SBOM is attached to.
+    pub fn subject(&self) -> Option<&Descriptor> {
+        match self {
+            Self::OciImage(manifest) => manifest.subject.as_ref(),
+            Self::OciIndex(index) => index.subject.as_ref(),
+            _ => None,
+        }
+    }
+
+    /// Describe the manifest as a referrer of its subject.
+    ///
+    /// The artifact type of an image manifest falls back to the media type of its config.
+    /// See https://github.com/opencontainers/distribution-spec/blob/main/spec.md#listing-referrers
+    pub fn describe(&self, media_type: &str, digest: ContentDigest, size: u64) -> Descriptor {
+        let (artifact_type, annotations): (Option<String>, Option<HashMap<String, String>>) = match self {
+            Self::OciImage(manifest) => (
+                manifest
+                    .artifact_type
+                    .clone()
+                    .or_else(|| Some(manifest.config.media_type.clone())),
+                manifest.annotations.clone(),
+            ),
+            Self::OciIndex(index) => (index.artifact_type.clone(), index.annotations.clone()),
+            _ => (None, None),
+        };
+        Descriptor {
+            media_type: media_type.to_string(),
+            digest,
+            size,
+            urls: None,
+            annotations,
+            artifact_type,
+            platform: None,
+        }
+    }
+
+    /// The digest of the image for the platform, if the manifest is a list of images for several platforms.
+    pub fn platform_manifest(&self, os: &str, architecture: &str) -> Option<&ContentDigest> {
+        match self {
+            Self::List(manifest_list) => manifest_list
+                .manifests
+                .iter()
+                .find(|item| item.platform.os == os && item.platform.architecture == architecture)
+                .map(|item| &item.digest),
+            Self::OciIndex(index) => index
+                .manifests
+                .iter()
+                .find(|descriptor| {
+                    descriptor.platform.as_ref().map_or(false, |platform| {
+                        platform.os == os && platform.architecture == architecture
+                    })
+                })
+                .map(|descriptor| &descriptor.digest),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse_by_media_type() {
+        let json = include_str!("../../tests/data/manifest_v2.json");
+        let manifest = Manifest::parse(ManifestV2::MIME_TYPE, json);
+        assert!(matches!(manifest, Ok(Manifest::V2(_))));
+
+        let manifest = manifest.unwrap();
+        assert_eq!(manifest.blobs().len(), 4);
+        assert!(manifest.manifests().is_empty());
+
+        let json = include_str!("../../tests/data/manifest_list.json");
+        let manifest = Manifest::parse(&format!("{}; charset=utf-8", ManifestList::MIME_TYPE), json);
+        assert!(matches!(manifest, Ok(Manifest::List(_))));
+        assert_eq!(manifest.unwrap().manifests().len(), 2);
+    }
+
+    #[test]
+    fn parse_oci_media_types() {
+        let json = include_str!("../../tests/data/oci_image_manifest.json");
+        let manifest = Manifest::parse(OciImageManifest::MIME_TYPE, json).unwrap();
+        assert!(matches!(manifest, Manifest::OciImage(_)));
+        assert_eq!(manifest.blobs().len(), 2);
+
+        let json = include_str!("../../tests/data/oci_image_index.json");
+        let manifest = Manifest::parse(OciImageIndex::MIME_TYPE, json).unwrap();
+        assert!(matches!(manifest, Manifest::OciIndex(_)));
+        assert_eq!(manifest.manifests().len(), 2);
+        assert_eq!(
+            manifest
+                .platform_manifest("linux", "arm64")
+                .map(|digest| digest.to_string()),
+            Some("sha256:5b0bcabd1ed22e9fb1310cf6c2dec7cdef19f0ad69efa1f392e94a4333501270".to_string())
+        );
+        assert!(manifest.platform_manifest("linux", "amd64").is_none());
+    }
+
+    #[test]
+    fn describe_referrer() {
+        let json = include_str!("../../tests/data/oci_image_manifest.json");
+        let manifest = Manifest::parse(OciImageManifest::MIME_TYPE, json).unwrap();
+        assert_eq!(
+            manifest.subject().map(|subject| subject.digest.to_string()),
+            Some("sha256:5b0bcabd1ed22e9fb1310cf6c2dec7cdef19f0ad69efa1f392e94a4333501270".to_string())
+        );
+
+        let digest = "sha256:9834876dcfb05cb167a5c24953eba58c4ac89b1adf57f28f2f9d09af107ee8f0"
+            .parse::<ContentDigest>()
+            .unwrap();
+        let descriptor = manifest.describe(OciImageManifest::MIME_TYPE, digest, json.len() as u64);
+        assert_eq!(
+            descriptor.artifact_type.as_deref(),
+            Some("application/vnd.example.sbom.v1")
+        );
+        assert!(descriptor.annotations.is_some());
+
+        let json = json.replace(r#""artifactType": "application/vnd.example.sbom.v1","#, "");
+        let manifest = Manifest::parse(OciImageManifest::MIME_TYPE, &json).unwrap();
+        let descriptor = manifest.describe(OciImageManifest::MIME_TYPE, descriptor.digest, json.len() as u64);
+        assert_eq!(
+            descriptor.artifact_type.as_deref(),
+            Some("application/vnd.oci.empty.v1+json")
+        );
+    }
+
+    #[test]
+    fn invalid_unsupported_media_type() {
+        let json = include_str!("../../tests/data/manifest_v2.json");
+        let manifest = Manifest::parse("application/json", json);
+        assert!(matches!(manifest, Err(RegistryError::ManifestInvalid { .. })));
+    }
+}
@@ -0,0 +1,66 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+use crate::digest;
+
+/// Describes the disposition of the targeted content.
+///
+/// See https://github.com/opencontainers/image-spec/blob/main/descriptor.md
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Descriptor {
+    /// The media type of the referenced content.
+    pub media_type: String,
+
+    /// The digest of the targeted content.
+    /// Retrieved content should be verified against this digest.
+    pub digest: digest::ContentDigest,
+
+    /// The size, in bytes, of the raw content.
+    /// This property exists so that a client will have an expected size for the content before processing.
+    pub size: u64,
+
+    /// A list of URIs from which this object may be downloaded.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub urls: Option<Vec<String>>,
+
+    /// Arbitrary metadata for this descriptor.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub annotations: Option<HashMap<String, String>>,
+
+    /// The type of an artifact when the descriptor points to an artifact.
+    /// This is the value of the config descriptor `mediaType` when the descriptor references an image manifest.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub artifact_type: Option<String>,
+
+    /// The minimum runtime requirements of the image.
+    /// This property should only be present when the descriptor references a platform-specific manifest.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub platform: Option<Platform>,
+}
+
+/// See https://github.com/opencontainers/image-spec/blob/main/image-index.md#image-index-property-descriptions
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Platform {
+    /// The CPU architecture, for example `amd64` or `ppc64le`.
+    pub architecture: String,
+
+    /// The operating system, for example `linux` or `windows`.
+    pub os: String,
+
+    /// The version of the operating system, for example `10.0.14393.1066` on `windows`.
+    #[serde(rename = "os.version", default, skip_serializing_if = "Option::is_none")]
+    pub os_version: Option<String>,
+
+    /// Mandatory OS features, for example `win32k` on `windows`.
+    #[serde(rename = "os.features", default, skip_serializing_if = "Option::is_none")]
+    pub os_features: Option<Vec<String>>,
+
+    /// The variant of the CPU, for example `v7` to specify ARMv7 when architecture is `arm`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub variant: Option<String>,
+
+    /// Reserved for future versions of the specification.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub features: Option<Vec<String>>,
+}
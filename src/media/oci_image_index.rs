@@ -0,0 +1,100 @@
+use serde::{Deserialize, Serialize};
+use serde_repr::{Deserialize_repr, Serialize_repr};
+use std::collections::HashMap;
+use std::str::FromStr;
+
+use super::oci_descriptor::Descriptor;
+use crate::errors::RegistryError;
+
+#[derive(Debug, Serialize_repr, Deserialize_repr)]
+#[repr(u8)]
+pub enum SchemaVersion {
+    X = 2,
+}
+
+/// See https://github.com/opencontainers/image-spec/blob/main/image-index.md#image-index-property-descriptions
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OciImageIndex {
+    /// This property specifies the image manifest schema version.
+    /// For this version of the specification, this must be `2` to ensure backward compatibility with older versions of Docker.
+    pub schema_version: SchemaVersion,
+
+    /// This property should be used and remain compatible with earlier versions of this specification and with other similar external formats.
+    /// When used, this field must contain the media type `application/vnd.oci.image.index.v1+json`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub media_type: Option<String>,
+
+    /// This optional property contains the type of an artifact when the index is used for an artifact.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub artifact_type: Option<String>,
+
+    /// This required property contains a list of manifests for specific platforms.
+    pub manifests: Vec<Descriptor>,
+
+    /// This optional property specifies a descriptor of another manifest.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub subject: Option<Descriptor>,
+
+    /// This optional property contains arbitrary metadata for the image index.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub annotations: Option<HashMap<String, String>>,
+}
+
+impl OciImageIndex {
+    pub const MIME_TYPE: &'static str = "application/vnd.oci.image.index.v1+json";
+}
+
+pub enum OciImageIndexError {
+    ParsingError(serde_json::Error),
+    MediaTypeMismatch(String),
+}
+
+impl From<serde_json::Error> for OciImageIndexError {
+    fn from(err: serde_json::Error) -> Self {
+        Self::ParsingError(err)
+    }
+}
+
+impl From<OciImageIndexError> for RegistryError {
+    fn from(err: OciImageIndexError) -> Self {
+        match err {
+            OciImageIndexError::ParsingError(err) => Self::ManifestInvalid {
+                detail: err.to_string(),
+            },
+            OciImageIndexError::MediaTypeMismatch(media_type) => Self::ManifestInvalid {
+                detail: format!("unexpected media type: {}", media_type),
+            },
+        }
+    }
+}
+
+impl FromStr for OciImageIndex {
+    type Err = OciImageIndexError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let index: Self = serde_json::from_str(s)?;
+        match &index.media_type {
+            Some(media_type) if media_type != Self::MIME_TYPE => {
+                Err(OciImageIndexError::MediaTypeMismatch(media_type.clone()))
+            }
+            _ => Ok(index),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse_oci_image_index() {
+        let json = include_str!("../../tests/data/oci_image_index.json");
+        let index = json.parse::<OciImageIndex>().ok().unwrap();
+        assert_eq!(index.manifests.len(), 2);
+
+        let platform = index.manifests[1].platform.as_ref().unwrap();
+        assert_eq!(platform.architecture, "arm64");
+        assert_eq!(platform.variant.as_deref(), Some("v8"));
+    }
+}
@@ -0,0 +1,124 @@
+use serde::{Deserialize, Serialize};
+use serde_repr::{Deserialize_repr, Serialize_repr};
+use std::collections::HashMap;
+use std::str::FromStr;
+
+use super::oci_descriptor::Descriptor;
+use crate::errors::RegistryError;
+
+#[derive(Debug, Serialize_repr, Deserialize_repr)]
+#[repr(u8)]
+pub enum SchemaVersion {
+    X = 2,
+}
+
+/// See https://github.com/opencontainers/image-spec/blob/main/manifest.md#image-manifest-property-descriptions
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OciImageManifest {
+    /// This property specifies the image manifest schema version.
+    /// For this version of the specification, this must be `2` to ensure backward compatibility with older versions of Docker.
+    pub schema_version: SchemaVersion,
+
+    /// This property should be used and remain compatible with earlier versions of this specification and with other similar external formats.
+    /// When used, this field must contain the media type `application/vnd.oci.image.manifest.v1+json`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub media_type: Option<String>,
+
+    /// This optional property contains the type of an artifact when the manifest is used for an artifact.
+    /// This must be set when `config.mediaType` is set to the empty value.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub artifact_type: Option<String>,
+
+    /// This required property references a configuration object for a container, by digest.
+    pub config: Descriptor,
+
+    /// Each item in the array must be a descriptor. For portability, layers should have at least one entry.
+    pub layers: Vec<Descriptor>,
+
+    /// This optional property specifies a descriptor of another manifest.
+    /// This value defines a weak association to a separate Merkle Directed Acyclic Graph (DAG) structure.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub subject: Option<Descriptor>,
+
+    /// This optional property contains arbitrary metadata for the image manifest.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub annotations: Option<HashMap<String, String>>,
+}
+
+impl OciImageManifest {
+    pub const MIME_TYPE: &'static str = "application/vnd.oci.image.manifest.v1+json";
+}
+
+pub enum OciImageManifestError {
+    ParsingError(serde_json::Error),
+    MediaTypeMismatch(String),
+}
+
+impl From<serde_json::Error> for OciImageManifestError {
+    fn from(err: serde_json::Error) -> Self {
+        Self::ParsingError(err)
+    }
+}
+
+impl From<OciImageManifestError> for RegistryError {
+    fn from(err: OciImageManifestError) -> Self {
+        match err {
+            OciImageManifestError::ParsingError(err) => Self::ManifestInvalid {
+                detail: err.to_string(),
+            },
+            OciImageManifestError::MediaTypeMismatch(media_type) => Self::ManifestInvalid {
+                detail: format!("unexpected media type: {}", media_type),
+            },
+        }
+    }
+}
+
+impl FromStr for OciImageManifest {
+    type Err = OciImageManifestError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let manifest: Self = serde_json::from_str(s)?;
+        match &manifest.media_type {
+            Some(media_type) if media_type != Self::MIME_TYPE => {
+                Err(OciImageManifestError::MediaTypeMismatch(media_type.clone()))
+            }
+            _ => Ok(manifest),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse_oci_image_manifest() {
+        let json = include_str!("../../tests/data/oci_image_manifest.json");
+        let manifest = json.parse::<OciImageManifest>().ok().unwrap();
+        assert_eq!(manifest.layers.len(), 1);
+        assert_eq!(
+            manifest.artifact_type.as_deref(),
+            Some("application/vnd.example.sbom.v1")
+        );
+        assert!(manifest.subject.is_some());
+        assert_eq!(
+            manifest
+                .annotations
+                .unwrap()
+                .get("org.opencontainers.image.created")
+                .map(String::as_str),
+            Some("2023-01-02T03:04:05Z")
+        );
+    }
+
+    #[test]
+    fn invalid_media_type() {
+        let json = include_str!("../../tests/data/oci_image_manifest.json")
+            .replace(OciImageManifest::MIME_TYPE, "application/vnd.oci.image.index.v1+json");
+        assert!(matches!(
+            json.parse::<OciImageManifest>(),
+            Err(OciImageManifestError::MediaTypeMismatch(_))
+        ));
+    }
+}
@@ -0,0 +1,115 @@
+use lazy_static::lazy_static;
+use regex::Regex;
+
+use crate::digest::ContentDigest;
+use crate::errors::RegistryError;
+
+/// The most characters a name may have, including the separators of its components.
+pub const NAME_MAX_LENGTH: usize = 255;
+
+lazy_static! {
+    static ref PATH_COMPONENT: Regex = Regex::new(r"^[a-z0-9]+(?:(?:\.|_|__|-+)[a-z0-9]+)*$").unwrap();
+    // `\w` of the grammar is ASCII only.
+    static ref TAG: Regex = Regex::new(r"(?-u)^\w[\w.-]{0,127}$").unwrap();
+}
+
+/// What a manifest is identified by in the path, either a tag or a digest.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Reference<'a> {
+    Tag(&'a str),
+    Digest(ContentDigest),
+}
+
+impl<'a> Reference<'a> {
+    /// Parse a reference, which is a digest if it has a colon since tags can't have one.
+    pub fn parse(reference: &'a str) -> Result<Self, RegistryError> {
+        if reference.contains(':') {
+            return Ok(Self::Digest(reference.parse()?));
+        }
+        validate_tag(reference)?;
+        Ok(Self::Tag(reference))
+    }
+}
+
+/// Validate a `$repository/$image` name, whose image may have several components, e.g. `registry/team/service/worker`.
+///
+/// See https://github.com/distribution/reference/blob/main/reference.go
+pub fn validate_name(name: &str) -> Result<(), RegistryError> {
+    let components: Vec<&str> = name.split('/').collect();
+    // The first component is the repository, which must be followed by the image.
+    if name.len() > NAME_MAX_LENGTH
+        || components.len() < 2
+        || !components.iter().all(|component| PATH_COMPONENT.is_match(component))
+    {
+        return Err(RegistryError::NameInvalid);
+    }
+    Ok(())
+}
+
+/// Validate a tag, which has up to 128 word characters, periods and dashes, and can't start with either of the latter.
+pub fn validate_tag(tag: &str) -> Result<(), RegistryError> {
+    if !TAG.is_match(tag) {
+        return Err(RegistryError::TagInvalid);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn validate_nested_names() {
+        assert!(validate_name("samalba/my-app").is_ok());
+        assert!(validate_name("registry/team/service/worker").is_ok());
+        assert!(validate_name("a0/b.c/d__e/f---g").is_ok());
+
+        assert!(validate_name("samalba").is_err());
+        assert!(validate_name("samalba/").is_err());
+        assert!(validate_name("samalba//my-app").is_err());
+        assert!(validate_name("Samalba/my-app").is_err());
+        assert!(validate_name("samalba/-my-app").is_err());
+        assert!(validate_name("samalba/my-app.").is_err());
+        assert!(validate_name("samalba/my___app").is_err());
+
+        let long = format!("samalba/{}", "a".repeat(NAME_MAX_LENGTH - 8));
+        assert!(validate_name(&long).is_ok());
+        assert!(validate_name(&format!("{}a", long)).is_err());
+    }
+
+    #[test]
+    fn validate_tags() {
+        assert!(validate_tag("latest").is_ok());
+        assert!(validate_tag("v1.2.3-rc.1").is_ok());
+        assert!(validate_tag("_build__42").is_ok());
+        assert!(validate_tag("A").is_ok());
+        assert!(validate_tag(&"a".repeat(128)).is_ok());
+
+        assert!(validate_tag("").is_err());
+        assert!(validate_tag(".hidden").is_err());
+        assert!(validate_tag("-rc").is_err());
+        assert!(validate_tag("feature/branch").is_err());
+        assert!(validate_tag("caf\u{e9}").is_err());
+        assert!(validate_tag("v1 ").is_err());
+        assert!(validate_tag(&"a".repeat(129)).is_err());
+    }
+
+    #[test]
+    fn parse_references() {
+        let digest = "sha256:6c3c624b58dbbcd3c0dd82b4c53f04194d1247c6eebdaab7c610cf7d66709b3b";
+        assert_eq!(Reference::parse("latest").unwrap(), Reference::Tag("latest"));
+        assert_eq!(
+            Reference::parse(digest).unwrap(),
+            Reference::Digest(digest.parse().unwrap())
+        );
+        assert!(matches!(Reference::parse("-latest"), Err(RegistryError::TagInvalid)));
+        assert!(matches!(
+            Reference::parse("md5:d41d8cd98f00b204e9800998ecf8427e"),
+            Err(RegistryError::DigestInvalid { .. })
+        ));
+        assert!(matches!(
+            Reference::parse("sha256:not-hex"),
+            Err(RegistryError::DigestInvalid { .. })
+        ));
+    }
+}
@@ -0,0 +1,244 @@
+use futures_util::{Stream, StreamExt};
+use serde::{Deserialize, Serialize};
+use worker::{Error, Result};
+
+use super::{BlobStorage, Metadata};
+use crate::digest::{ContentDigest, ContentHasher};
+
+/// Size of each part of a R2 multipart upload.
+///
+/// R2 requires every part except the last one to be the same size, and at least 5 MiB.
+pub const PART_SIZE: usize = 10 * 1024 * 1024;
+
+/// Progress of a `BlobWriter`, which can be saved to resume writing in another invocation.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct WriterState {
+    /// The id of the underlying multipart upload, if content exceeded a single part.
+    pub upload_id: Option<String>,
+
+    /// ETags of the uploaded parts. The part number of each is its index plus one.
+    pub part_etags: Vec<String>,
+
+    /// The number of bytes written so far.
+    pub size: u64,
+
+    /// The state of hashing the bytes written so far.
+    pub hasher: ContentHasher,
+
+    /// The digest of the bytes kept in the `.tail` object, so that a tail written over since is never resumed.
+    pub tail_digest: Option<ContentDigest>,
+}
+
+impl WriterState {
+    /// The number of bytes written but not uploaded as a part yet.
+    fn buffered_size(&self) -> usize {
+        (self.size - (self.part_etags.len() * PART_SIZE) as u64) as usize
+    }
+}
+
+/// Writes content into an object while computing its digest on the fly.
+///
+/// Content up to `PART_SIZE` is stored by a single `put`, anything larger goes through a multipart upload
+/// so that the whole content never has to be held in memory. Nothing is visible under the key until `commit`.
+///
+/// Bytes which don't fill a whole part yet are kept in a `.tail` object next to the key while suspended.
+pub struct BlobWriter<'a, B: BlobStorage> {
+    blobs: &'a B,
+    key: String,
+    buffer: Vec<u8>,
+    state: WriterState,
+}
+
+impl<'a, B: BlobStorage> BlobWriter<'a, B> {
+    pub fn new(blobs: &'a B, key: impl Into<String>) -> Self {
+        Self {
+            blobs,
+            key: key.into(),
+            buffer: Vec::new(),
+            state: WriterState::default(),
+        }
+    }
+
+    /// Continue writing from a state previously returned by `suspend`.
+    pub async fn resume(blobs: &'a B, key: impl Into<String>, state: WriterState) -> Result<BlobWriter<'a, B>> {
+        let key = key.into();
+        let mut buffer = Vec::new();
+        if state.buffered_size() > 0 {
+            if let Some((_, tail)) = blobs.get(&tail_key(&key)).await? {
+                buffer = tail;
+            }
+            let mut hasher = ContentHasher::new();
+            hasher.update(&buffer);
+            if buffer.len() != state.buffered_size() || state.tail_digest != Some(hasher.digest()) {
+                return Err(Error::RustError(format!("tail of {} is missing or corrupted", key)));
+            }
+        }
+        Ok(Self {
+            blobs,
+            key,
+            buffer,
+            state,
+        })
+    }
+
+    /// The number of bytes written so far.
+    pub fn size(&self) -> u64 {
+        self.state.size
+    }
+
+    /// The digest of the bytes written so far, including those written before being suspended.
+    pub fn digest(&self) -> ContentDigest {
+        self.state.hasher.digest()
+    }
+
+    pub async fn write(&mut self, chunk: &[u8]) -> Result<()> {
+        self.state.hasher.update(chunk);
+        self.state.size += chunk.len() as u64;
+        self.buffer.extend_from_slice(chunk);
+
+        while self.buffer.len() >= PART_SIZE {
+            let rest = self.buffer.split_off(PART_SIZE);
+            let part = std::mem::replace(&mut self.buffer, rest);
+            self.upload_part(part).await?;
+        }
+        Ok(())
+    }
+
+    /// Write every chunk of the stream, e.g. a request body.
+    pub async fn write_stream<S>(&mut self, mut stream: S) -> Result<()>
+    where
+        S: Stream<Item = Result<Vec<u8>>> + Unpin,
+    {
+        while let Some(chunk) = stream.next().await {
+            self.write(&chunk?).await?;
+        }
+        Ok(())
+    }
+
+    /// Save the buffered bytes so that writing can be resumed later.
+    pub async fn suspend(mut self) -> Result<WriterState> {
+        if self.buffer.is_empty() {
+            self.state.tail_digest = None;
+            self.blobs.delete(&tail_key(&self.key)).await?;
+        } else {
+            let mut hasher = ContentHasher::new();
+            hasher.update(&self.buffer);
+            self.state.tail_digest = Some(hasher.digest());
+            self.blobs
+                .put(&tail_key(&self.key), self.buffer, Metadata::default())
+                .await?;
+        }
+        Ok(self.state)
+    }
+
+    /// Make the written content available under the key.
+    pub async fn commit(mut self) -> Result<()> {
+        match self.state.upload_id.clone() {
+            None => {
+                self.blobs.put(&self.key, self.buffer, Metadata::default()).await?;
+            }
+            Some(upload_id) => {
+                if !self.buffer.is_empty() {
+                    let part = std::mem::take(&mut self.buffer);
+                    self.upload_part(part).await?;
+                }
+                self.blobs
+                    .complete_multipart_upload(&self.key, &upload_id, &self.state.part_etags)
+                    .await?;
+            }
+        }
+        self.blobs.delete(&tail_key(&self.key)).await
+    }
+
+    /// Discard the written content.
+    pub async fn abort(self) -> Result<()> {
+        discard(self.blobs, &self.key, &self.state).await
+    }
+
+    async fn upload_part(&mut self, part: Vec<u8>) -> Result<()> {
+        let upload_id = match self.state.upload_id.clone() {
+            Some(upload_id) => upload_id,
+            None => self.blobs.create_multipart_upload(&self.key).await?,
+        };
+        self.state.upload_id = Some(upload_id.clone());
+
+        let part_number = self.state.part_etags.len() as u16 + 1;
+        let etag = self.blobs.upload_part(&self.key, &upload_id, part_number, part).await?;
+        self.state.part_etags.push(etag);
+        Ok(())
+    }
+}
+
+/// Discard the content written by a suspended writer without resuming it.
+pub async fn discard<B: BlobStorage>(blobs: &B, key: &str, state: &WriterState) -> Result<()> {
+    if let Some(upload_id) = &state.upload_id {
+        blobs.abort_multipart_upload(key, upload_id).await?;
+    }
+    blobs.delete(&tail_key(key)).await
+}
+
+fn tail_key(key: &str) -> String {
+    format!("{}.tail", key)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::storage::memory::MemoryBlobStorage;
+    use futures_executor::block_on;
+
+    #[test]
+    fn resume_across_parts() {
+        let blobs = MemoryBlobStorage::default();
+        let content: Vec<u8> = (0..PART_SIZE * 2 + 3).map(|i| i as u8).collect();
+        let (first, rest) = content.split_at(PART_SIZE + 1);
+        block_on(async {
+            let mut writer = BlobWriter::new(&blobs, "blob");
+            writer.write(first).await.unwrap();
+            let state = writer.suspend().await.unwrap();
+            assert_eq!(state.part_etags.len(), 1);
+            assert_eq!(blobs.keys(), vec!["blob.tail".to_string()]);
+
+            let mut writer = BlobWriter::resume(&blobs, "blob", state).await.unwrap();
+            writer.write(rest).await.unwrap();
+            let mut hasher = ContentHasher::new();
+            hasher.update(&content);
+            assert_eq!(writer.digest(), hasher.digest());
+            writer.commit().await.unwrap();
+
+            assert_eq!(blobs.keys(), vec!["blob".to_string()]);
+            assert_eq!(blobs.get("blob").await.unwrap().unwrap().1, content);
+            assert!(blobs.pending_uploads().is_empty());
+        });
+    }
+
+    #[test]
+    fn reject_overwritten_tail() {
+        let blobs = MemoryBlobStorage::default();
+        block_on(async {
+            let mut writer = BlobWriter::new(&blobs, "blob");
+            writer.write(b"hello ").await.unwrap();
+            let state = writer.suspend().await.unwrap();
+
+            // Another writer resumed from the same state has written a tail of the same length.
+            blobs
+                .put("blob.tail", b"howdy ".to_vec(), Metadata::default())
+                .await
+                .unwrap();
+            assert!(BlobWriter::resume(&blobs, "blob", state).await.is_err());
+        });
+    }
+
+    #[test]
+    fn discard_suspended() {
+        let blobs = MemoryBlobStorage::default();
+        block_on(async {
+            let mut writer = BlobWriter::new(&blobs, "blob");
+            writer.write(&vec![0; PART_SIZE + 1]).await.unwrap();
+            let state = writer.suspend().await.unwrap();
+            discard(&blobs, "blob", &state).await.unwrap();
+            assert!(blobs.keys().is_empty());
+            assert!(blobs.pending_uploads().is_empty());
+        });
+    }
+}
@@ -0,0 +1,45 @@
+use serde::{de::DeserializeOwned, Serialize};
+use worker::wasm_bindgen::JsValue;
+use worker::{ListOptions, Result, Storage};
+
+use super::EntityStorage;
+
+impl EntityStorage for Storage {
+    async fn get<T: DeserializeOwned>(&self, key: &str) -> Result<Option<T>> {
+        // `Storage::get` fails on a missing key just like on any other error, which can't be told apart.
+        let value = self.get_multiple(vec![key]).await?.get(&JsValue::from_str(key));
+        if value.is_undefined() {
+            return Ok(None);
+        }
+        Ok(Some(serde_wasm_bindgen::from_value(value)?))
+    }
+
+    async fn put<T: Serialize>(&mut self, key: &str, value: &T) -> Result<()> {
+        Storage::put(self, key, value).await
+    }
+
+    async fn delete(&mut self, key: &str) -> Result<bool> {
+        Storage::delete(self, key).await
+    }
+
+    async fn delete_all(&mut self) -> Result<()> {
+        Storage::delete_all(self).await
+    }
+
+    async fn list(&self, prefix: &str, start: Option<&str>, limit: Option<usize>) -> Result<Vec<String>> {
+        let mut options = ListOptions::new().prefix(prefix);
+        if let Some(start) = start {
+            options = options.start(start);
+        }
+        if let Some(limit) = limit {
+            options = options.limit(limit);
+        }
+        Ok(self
+            .list_with_options(options)
+            .await?
+            .keys()
+            .into_iter()
+            .filter_map(|key| key.ok().and_then(|key| key.as_string()))
+            .collect())
+    }
+}
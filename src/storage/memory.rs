@@ -0,0 +1,272 @@
+//! Storages held in memory, which serve the registry in tests without any Cloudflare binding.
+
+use serde::{de::DeserializeOwned, Serialize};
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap};
+use std::ops::Bound;
+use std::rc::Rc;
+use worker::{Error, Result};
+
+use super::{BlobStorage, EntityStorage, Metadata, ObjectInfo};
+
+/// A multipart upload in progress, whose parts are keyed by their ETags.
+#[derive(Debug, Default)]
+struct MultipartUpload {
+    key: String,
+    parts: HashMap<String, Vec<u8>>,
+}
+
+#[derive(Debug, Default)]
+struct Objects {
+    objects: BTreeMap<String, (ObjectInfo, Vec<u8>)>,
+    uploads: HashMap<String, MultipartUpload>,
+    next_id: usize,
+    /// The time objects are written at, in seconds since the epoch.
+    now: u64,
+}
+
+impl Objects {
+    fn insert(&mut self, key: &str, value: Vec<u8>, metadata: Metadata) {
+        let info = ObjectInfo {
+            size: value.len() as u64,
+            uploaded: self.now,
+            metadata,
+        };
+        self.objects.insert(key.to_string(), (info, value));
+    }
+}
+
+/// Objects held in memory. Clones share the same objects.
+#[derive(Debug, Clone, Default)]
+pub struct MemoryBlobStorage {
+    inner: Rc<RefCell<Objects>>,
+}
+
+impl MemoryBlobStorage {
+    /// Keys of every object, in lexical order.
+    pub fn keys(&self) -> Vec<String> {
+        self.inner.borrow().objects.keys().cloned().collect()
+    }
+
+    /// Keys of the multipart uploads neither completed nor aborted yet.
+    pub fn pending_uploads(&self) -> Vec<String> {
+        self.inner
+            .borrow()
+            .uploads
+            .values()
+            .map(|upload| upload.key.clone())
+            .collect()
+    }
+
+    /// Set the time objects written from now on are uploaded at.
+    pub fn set_now(&self, now: u64) {
+        self.inner.borrow_mut().now = now;
+    }
+}
+
+impl BlobStorage for MemoryBlobStorage {
+    type Stream = Vec<u8>;
+
+    async fn head(&self, key: &str) -> Result<Option<ObjectInfo>> {
+        Ok(self.get(key).await?.map(|(info, _)| info))
+    }
+
+    async fn get(&self, key: &str) -> Result<Option<(ObjectInfo, Vec<u8>)>> {
+        Ok(self.inner.borrow().objects.get(key).cloned())
+    }
+
+    async fn stream(&self, key: &str, range: Option<(u64, u64)>) -> Result<Option<Vec<u8>>> {
+        Ok(self.get(key).await?.map(|(_, body)| match range {
+            Some((start, end)) => body[start as usize..=end as usize].to_vec(),
+            None => body,
+        }))
+    }
+
+    async fn put(&self, key: &str, value: Vec<u8>, metadata: Metadata) -> Result<()> {
+        self.inner.borrow_mut().insert(key, value, metadata);
+        Ok(())
+    }
+
+    async fn delete(&self, key: &str) -> Result<()> {
+        self.inner.borrow_mut().objects.remove(key);
+        Ok(())
+    }
+
+    async fn list(&self, prefix: &str, start_after: Option<&str>, limit: usize) -> Result<(Vec<String>, bool)> {
+        let inner = self.inner.borrow();
+        let start = match start_after {
+            Some(start_after) if start_after >= prefix => Bound::Excluded(start_after.to_string()),
+            _ => Bound::Included(prefix.to_string()),
+        };
+        let mut keys = inner
+            .objects
+            .range((start, Bound::Unbounded))
+            .map(|(key, _)| key)
+            .take_while(|key| key.starts_with(prefix))
+            .filter(|key| !key[prefix.len()..].contains('/'))
+            .take(limit + 1)
+            .cloned()
+            .collect::<Vec<_>>();
+        let truncated = keys.len() > limit;
+        keys.truncate(limit);
+        Ok((keys, truncated))
+    }
+
+    async fn create_multipart_upload(&self, key: &str) -> Result<String> {
+        let mut inner = self.inner.borrow_mut();
+        inner.next_id += 1;
+        let upload_id = format!("upload-{}", inner.next_id);
+        let upload = MultipartUpload {
+            key: key.to_string(),
+            parts: HashMap::new(),
+        };
+        inner.uploads.insert(upload_id.clone(), upload);
+        Ok(upload_id)
+    }
+
+    async fn upload_part(&self, key: &str, upload_id: &str, part_number: u16, part: Vec<u8>) -> Result<String> {
+        let mut inner = self.inner.borrow_mut();
+        let upload = upload(&mut inner.uploads, key, upload_id)?;
+        let etag = format!("{}-{}", upload_id, part_number);
+        upload.parts.insert(etag.clone(), part);
+        Ok(etag)
+    }
+
+    async fn complete_multipart_upload(&self, key: &str, upload_id: &str, part_etags: &[String]) -> Result<()> {
+        let mut inner = self.inner.borrow_mut();
+        let upload = upload(&mut inner.uploads, key, upload_id)?;
+        let mut body = Vec::new();
+        for etag in part_etags {
+            match upload.parts.get(etag) {
+                Some(part) => body.extend_from_slice(part),
+                None => return Err(Error::RustError(format!("part {} of {} is missing", etag, key))),
+            }
+        }
+        inner.uploads.remove(upload_id);
+        inner.insert(key, body, Metadata::default());
+        Ok(())
+    }
+
+    async fn abort_multipart_upload(&self, key: &str, upload_id: &str) -> Result<()> {
+        let mut inner = self.inner.borrow_mut();
+        upload(&mut inner.uploads, key, upload_id)?;
+        inner.uploads.remove(upload_id);
+        Ok(())
+    }
+}
+
+fn upload<'a>(
+    uploads: &'a mut HashMap<String, MultipartUpload>,
+    key: &str,
+    upload_id: &str,
+) -> Result<&'a mut MultipartUpload> {
+    match uploads.get_mut(upload_id) {
+        Some(upload) if upload.key == key => Ok(upload),
+        _ => Err(Error::RustError(format!(
+            "no multipart upload {} of {}",
+            upload_id, key
+        ))),
+    }
+}
+
+/// The state of an entity held in memory. Clones share the same state.
+///
+/// Values are kept serialized, so that they go through the same round trip as in a Durable Object.
+#[derive(Debug, Clone, Default)]
+pub struct MemoryEntityStorage {
+    values: Rc<RefCell<BTreeMap<String, serde_json::Value>>>,
+}
+
+impl EntityStorage for MemoryEntityStorage {
+    async fn get<T: DeserializeOwned>(&self, key: &str) -> Result<Option<T>> {
+        match self.values.borrow().get(key) {
+            Some(value) => Ok(Some(serde_json::from_value(value.clone())?)),
+            None => Ok(None),
+        }
+    }
+
+    async fn put<T: Serialize>(&mut self, key: &str, value: &T) -> Result<()> {
+        let value = serde_json::to_value(value)?;
+        self.values.borrow_mut().insert(key.to_string(), value);
+        Ok(())
+    }
+
+    async fn delete(&mut self, key: &str) -> Result<bool> {
+        Ok(self.values.borrow_mut().remove(key).is_some())
+    }
+
+    async fn delete_all(&mut self) -> Result<()> {
+        self.values.borrow_mut().clear();
+        Ok(())
+    }
+
+    async fn list(&self, prefix: &str, start: Option<&str>, limit: Option<usize>) -> Result<Vec<String>> {
+        let start = start.unwrap_or(prefix).max(prefix).to_string();
+        Ok(self
+            .values
+            .borrow()
+            .range(start..)
+            .map(|(key, _)| key)
+            .take_while(|key| key.starts_with(prefix))
+            .take(limit.unwrap_or(usize::MAX))
+            .cloned()
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use futures_executor::block_on;
+
+    #[test]
+    fn list_direct_children() {
+        let blobs = MemoryBlobStorage::default();
+        block_on(async {
+            for key in [
+                "a/tags/v1",
+                "a/tags/v2",
+                "a/tags/v3",
+                "a/tags/nested/tags/v1",
+                "a/tagsx",
+            ] {
+                blobs.put(key, Vec::new(), Metadata::default()).await.unwrap();
+            }
+            assert_eq!(
+                blobs.list("a/tags/", None, 2).await.unwrap(),
+                (vec!["a/tags/v1".to_string(), "a/tags/v2".to_string()], true)
+            );
+            assert_eq!(
+                blobs.list("a/tags/", Some("a/tags/v2"), 2).await.unwrap(),
+                (vec!["a/tags/v3".to_string()], false)
+            );
+        });
+    }
+
+    #[test]
+    fn complete_multipart_upload() {
+        let blobs = MemoryBlobStorage::default();
+        block_on(async {
+            let upload_id = blobs.create_multipart_upload("blob").await.unwrap();
+            let first = blobs
+                .upload_part("blob", &upload_id, 1, b"hello ".to_vec())
+                .await
+                .unwrap();
+            let second = blobs
+                .upload_part("blob", &upload_id, 2, b"world".to_vec())
+                .await
+                .unwrap();
+            assert!(blobs.head("blob").await.unwrap().is_none());
+
+            blobs
+                .complete_multipart_upload("blob", &upload_id, &[first, second])
+                .await
+                .unwrap();
+            assert_eq!(
+                blobs.stream("blob", Some((6, 10))).await.unwrap(),
+                Some(b"world".to_vec())
+            );
+            assert!(blobs.pending_uploads().is_empty());
+        });
+    }
+}
@@ -0,0 +1,448 @@
+pub mod blob_writer;
+pub mod durable;
+#[cfg(test)]
+pub mod memory;
+pub mod r2;
+
+use serde::{de::DeserializeOwned, Serialize};
+use std::collections::HashMap;
+use worker::{Error, Result};
+
+use crate::digest::ContentDigest;
+use crate::media::oci_descriptor::Descriptor;
+
+/// Name of the R2 bucket binding which holds blobs. See `wrangler.toml`.
+pub const BUCKET_BINDING: &str = "REGISTRY_BUCKET";
+
+/// Custom metadata of a blob object whose content is stored under another key.
+pub const LINK_METADATA: &str = "link";
+
+/// What the slashes of an image name are replaced with in the key of a link, to keep it a single component.
+const ESCAPED_SEPARATOR: &str = "%2F";
+
+/// Object key of a blob linked into `$repository/$image`, which refers to the content stored by its digest.
+pub fn blob_key(repository_name: &str, image_name: &str, digest: &ContentDigest) -> String {
+    format!("{}{}", blobs_prefix(repository_name, image_name), digest)
+}
+
+/// Prefix of the keys of every blob linked into `$repository/$image`.
+pub fn blobs_prefix(repository_name: &str, image_name: &str) -> String {
+    format!("{}/{}/blobs/", repository_name, image_name)
+}
+
+/// Object key of the content of a blob, stored once for every repository.
+///
+/// Names never start with `_`, so that no image can be confused with the store.
+pub fn content_key(digest: &ContentDigest) -> String {
+    format!("{}{}", CONTENTS_PREFIX, digest)
+}
+
+/// Prefix of the keys of the content of every blob.
+pub const CONTENTS_PREFIX: &str = "_blobs/";
+
+/// Object key which counts a reference from `$repository/$image` to the content of a blob.
+pub fn reference_key(digest: &ContentDigest, repository_name: &str, image_name: &str) -> String {
+    format!(
+        "{}{}{}{}",
+        references_prefix(digest),
+        repository_name,
+        ESCAPED_SEPARATOR,
+        image_name.replace('/', ESCAPED_SEPARATOR)
+    )
+}
+
+fn references_prefix(digest: &ContentDigest) -> String {
+    format!("{}/references/", content_key(digest))
+}
+
+/// Object key of a manifest stored under `$repository/$image`.
+pub fn manifest_key(repository_name: &str, image_name: &str, digest: &ContentDigest) -> String {
+    format!("{}{}", manifests_prefix(repository_name, image_name), digest)
+}
+
+/// Prefix of the keys of every manifest under `$repository/$image`.
+pub fn manifests_prefix(repository_name: &str, image_name: &str) -> String {
+    format!("{}/{}/manifests/", repository_name, image_name)
+}
+
+/// Object key of a tag under `$repository/$image`, which holds the digest of the manifest it points to.
+pub fn tag_key(repository_name: &str, image_name: &str, tag: &str) -> String {
+    format!("{}{}", tags_prefix(repository_name, image_name), tag)
+}
+
+/// Prefix of the keys of every tag under `$repository/$image`.
+pub fn tags_prefix(repository_name: &str, image_name: &str) -> String {
+    format!("{}/{}/tags/", repository_name, image_name)
+}
+
+/// Object key of an entry of the tags which point to the manifest of the digest, which is empty.
+///
+/// Tags are indexed by the manifest they point to, so that deleting a manifest doesn't go through every tag.
+pub fn tagged_key(repository_name: &str, image_name: &str, digest: &ContentDigest, tag: &str) -> String {
+    format!("{}{}", tagged_prefix(repository_name, image_name, digest), tag)
+}
+
+fn tagged_prefix(repository_name: &str, image_name: &str, digest: &ContentDigest) -> String {
+    format!("{}/{}/tagged/{}/", repository_name, image_name, digest)
+}
+
+/// Object key of a manifest which refers to `subject`, which holds the descriptor of the referrer.
+///
+/// Each referrer is stored on its own, so that concurrent pushes never overwrite each other.
+pub fn referrer_key(
+    repository_name: &str,
+    image_name: &str,
+    subject: &ContentDigest,
+    digest: &ContentDigest,
+) -> String {
+    format!("{}{}", referrers_prefix(repository_name, image_name, subject), digest)
+}
+
+fn referrers_prefix(repository_name: &str, image_name: &str, subject: &ContentDigest) -> String {
+    format!("{}/{}/referrers/{}/", repository_name, image_name, subject)
+}
+
+/// Object key the content of a resumable upload is written into.
+///
+/// The content stays there once uploaded, referred to by the key of its digest.
+pub fn upload_key(uuid: &str) -> String {
+    format!("_uploads/{}/data", uuid)
+}
+
+/// Metadata stored along with the content of an object.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Metadata {
+    /// The media type the content was pushed with.
+    pub content_type: Option<String>,
+
+    /// Custom metadata, e.g. the key of the object a blob is linked to.
+    pub custom: HashMap<String, String>,
+}
+
+/// Metadata of a stored object.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ObjectInfo {
+    /// The size of the content in bytes.
+    pub size: u64,
+
+    /// When the object was written, in seconds since the epoch.
+    pub uploaded: u64,
+
+    pub metadata: Metadata,
+}
+
+/// Holds blobs, manifests and tags as objects, i.e. R2.
+///
+/// Keys are `/` separated paths, e.g. `$repository/$image/blobs/$digest`.
+pub trait BlobStorage {
+    /// The content of an object streamed to a client without being held in memory.
+    type Stream;
+
+    async fn head(&self, key: &str) -> Result<Option<ObjectInfo>>;
+
+    /// Read the whole content of an object, which is meant for small ones such as manifests.
+    async fn get(&self, key: &str) -> Result<Option<(ObjectInfo, Vec<u8>)>>;
+
+    /// Stream the content of an object, or the inclusive range of bytes of it.
+    async fn stream(&self, key: &str, range: Option<(u64, u64)>) -> Result<Option<Self::Stream>>;
+
+    async fn put(&self, key: &str, value: Vec<u8>, metadata: Metadata) -> Result<()>;
+
+    async fn delete(&self, key: &str) -> Result<()>;
+
+    /// List up to `limit` keys under the prefix in lexical order, starting after the given key.
+    ///
+    /// Only keys right under the prefix are listed, not the ones of images nested in the image of the prefix,
+    /// e.g. `$repository/$image/tags/$name/tags/...` when listing `$repository/$image/tags/`.
+    ///
+    /// Returns whether more keys are left to be listed along with them.
+    async fn list(&self, prefix: &str, start_after: Option<&str>, limit: usize) -> Result<(Vec<String>, bool)>;
+
+    /// Start uploading content larger than a single request by parts, returning the id of the upload.
+    async fn create_multipart_upload(&self, key: &str) -> Result<String>;
+
+    /// Upload a part of a multipart upload, returning its ETag.
+    async fn upload_part(&self, key: &str, upload_id: &str, part_number: u16, part: Vec<u8>) -> Result<String>;
+
+    /// Make the content of the parts available under the key, in the order of their ETags.
+    async fn complete_multipart_upload(&self, key: &str, upload_id: &str, part_etags: &[String]) -> Result<()>;
+
+    async fn abort_multipart_upload(&self, key: &str, upload_id: &str) -> Result<()>;
+}
+
+/// Holds the state of a single entity, i.e. the transactional storage of a Durable Object.
+///
+/// Only the entity itself accesses its storage, so that every change to the state is serialized.
+pub trait EntityStorage {
+    async fn get<T: DeserializeOwned>(&self, key: &str) -> Result<Option<T>>;
+
+    async fn put<T: Serialize>(&mut self, key: &str, value: &T) -> Result<()>;
+
+    /// Returns whether there was a value under the key.
+    async fn delete(&mut self, key: &str) -> Result<bool>;
+
+    async fn delete_all(&mut self) -> Result<()>;
+
+    /// List up to `limit` keys under the prefix in lexical order, starting from `start` inclusively.
+    async fn list(&self, prefix: &str, start: Option<&str>, limit: Option<usize>) -> Result<Vec<String>>;
+}
+
+/// Put an object which refers to the content stored under `target_key`.
+async fn put_link<B: BlobStorage>(blobs: &B, key: &str, target_key: &str) -> Result<()> {
+    let metadata = Metadata {
+        custom: HashMap::from([(LINK_METADATA.to_string(), target_key.to_string())]),
+        ..Default::default()
+    };
+    blobs.put(key, Vec::new(), metadata).await
+}
+
+/// Whether the content of the digest is stored, whichever repository pushed it.
+pub async fn has_content<B: BlobStorage>(blobs: &B, digest: &ContentDigest) -> Result<bool> {
+    Ok(blobs.head(&content_key(digest)).await?.is_some())
+}
+
+/// Store the content of the digest, which has already been written under `upload_key`.
+///
+/// Uploaded content can't be moved once its digest is known, so the content key only refers to it.
+/// If the content is already stored, the upload is deleted instead.
+pub async fn store_upload<B: BlobStorage>(blobs: &B, digest: &ContentDigest, upload_key: &str) -> Result<()> {
+    if has_content(blobs, digest).await? {
+        return blobs.delete(upload_key).await;
+    }
+    put_link(blobs, &content_key(digest), upload_key).await
+}
+
+/// Make the stored content of the digest visible as a blob of `$repository/$image`.
+///
+/// The reference is counted before the blob becomes visible, so that stored content is never left unreferenced
+/// while a repository can see it. The content may have been collected as garbage after it was found though,
+/// in which case the blob is unlinked again and `false` is returned.
+pub async fn link_blob<B: BlobStorage>(
+    blobs: &B,
+    repository_name: &str,
+    image_name: &str,
+    digest: &ContentDigest,
+) -> Result<bool> {
+    let key = reference_key(digest, repository_name, image_name);
+    blobs.put(&key, Vec::new(), Metadata::default()).await?;
+    put_link(
+        blobs,
+        &blob_key(repository_name, image_name, digest),
+        &content_key(digest),
+    )
+    .await?;
+
+    if !has_content(blobs, digest).await? {
+        unlink_blob(blobs, repository_name, image_name, digest).await?;
+        return Ok(false);
+    }
+    Ok(true)
+}
+
+/// Remove the blob from `$repository/$image`, leaving the content to the repositories which still link it.
+///
+/// Content is never deleted along with its last link, since another push may be linking it at the same time.
+/// Returns whether the blob was linked.
+pub async fn unlink_blob<B: BlobStorage>(
+    blobs: &B,
+    repository_name: &str,
+    image_name: &str,
+    digest: &ContentDigest,
+) -> Result<bool> {
+    let key = blob_key(repository_name, image_name, digest);
+    if blobs.head(&key).await?.is_none() {
+        return Ok(false);
+    }
+    blobs.delete(&key).await?;
+    blobs
+        .delete(&reference_key(digest, repository_name, image_name))
+        .await?;
+    Ok(true)
+}
+
+/// The `$repository/$image` names which refer to the content of the digest, i.e. count its references.
+pub async fn list_references<B: BlobStorage>(blobs: &B, digest: &ContentDigest) -> Result<Vec<String>> {
+    let prefix = references_prefix(digest);
+    Ok(list_all(blobs, &prefix)
+        .await?
+        .iter()
+        .map(|key| key[prefix.len()..].replace(ESCAPED_SEPARATOR, "/"))
+        .collect())
+}
+
+/// Every key right under the prefix, listed a page at a time.
+pub async fn list_all<B: BlobStorage>(blobs: &B, prefix: &str) -> Result<Vec<String>> {
+    /// The most keys listed at once.
+    const PAGE_SIZE: usize = 1000;
+
+    let mut keys = Vec::new();
+    loop {
+        let (page, truncated) = blobs.list(prefix, keys.last().map(String::as_str), PAGE_SIZE).await?;
+        keys.extend(page);
+        if !truncated {
+            return Ok(keys);
+        }
+    }
+}
+
+/// Metadata of a stored blob.
+pub struct BlobInfo {
+    /// The key of the object holding the content.
+    pub key: String,
+
+    /// The size of the content in bytes.
+    pub size: u64,
+}
+
+/// Find the blob linked into `$repository/$image`, following the links to the object holding its content.
+pub async fn find_blob<B: BlobStorage>(
+    blobs: &B,
+    repository_name: &str,
+    image_name: &str,
+    digest: &ContentDigest,
+) -> Result<Option<BlobInfo>> {
+    /// A blob refers to the content of its digest, which may refer to the upload it was written by.
+    const MAX_LINKS: usize = 2;
+
+    let mut key = blob_key(repository_name, image_name, digest);
+    for _ in 0..=MAX_LINKS {
+        let object = match blobs.head(&key).await? {
+            Some(object) => object,
+            None => return Ok(None),
+        };
+        match object.metadata.custom.get(LINK_METADATA) {
+            Some(target_key) => key = target_key.clone(),
+            None => return Ok(Some(BlobInfo { size: object.size, key })),
+        }
+    }
+    Err(Error::RustError(format!("too many links to the blob {}", digest)))
+}
+
+/// Find the manifest stored under `$repository/$image`, along with the media type it was pushed with.
+pub async fn find_manifest<B: BlobStorage>(
+    blobs: &B,
+    repository_name: &str,
+    image_name: &str,
+    digest: &ContentDigest,
+) -> Result<Option<(String, Vec<u8>)>> {
+    let key = manifest_key(repository_name, image_name, digest);
+    Ok(blobs
+        .get(&key)
+        .await?
+        .map(|(object, body)| (object.metadata.content_type.unwrap_or_default(), body)))
+}
+
+/// Find the digest of the manifest the tag points to.
+pub async fn find_tag<B: BlobStorage>(
+    blobs: &B,
+    repository_name: &str,
+    image_name: &str,
+    tag: &str,
+) -> Result<Option<String>> {
+    let key = tag_key(repository_name, image_name, tag);
+    Ok(blobs
+        .get(&key)
+        .await?
+        .map(|(_, body)| String::from_utf8_lossy(&body).into_owned()))
+}
+
+/// Point the tag to the manifest of the digest, moving it from the one it pointed to before if any.
+///
+/// The tag is indexed before it's put, so that the index never misses it if anything fails in between.
+pub async fn put_tag<B: BlobStorage>(
+    blobs: &B,
+    repository_name: &str,
+    image_name: &str,
+    tag: &str,
+    digest: &ContentDigest,
+) -> Result<()> {
+    let previous = find_tag(blobs, repository_name, image_name, tag).await?;
+    blobs
+        .put(
+            &tagged_key(repository_name, image_name, digest, tag),
+            Vec::new(),
+            Metadata::default(),
+        )
+        .await?;
+    blobs
+        .put(
+            &tag_key(repository_name, image_name, tag),
+            digest.to_string().into_bytes(),
+            Metadata::default(),
+        )
+        .await?;
+    match previous.map(|previous| previous.parse::<ContentDigest>()) {
+        Some(Ok(previous)) if previous != *digest => {
+            blobs
+                .delete(&tagged_key(repository_name, image_name, &previous, tag))
+                .await
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Delete the tag along with its entry in the index, returning `false` if there's no such tag.
+pub async fn delete_tag<B: BlobStorage>(blobs: &B, repository_name: &str, image_name: &str, tag: &str) -> Result<bool> {
+    let digest = match find_tag(blobs, repository_name, image_name, tag).await? {
+        Some(digest) => digest,
+        None => return Ok(false),
+    };
+    blobs.delete(&tag_key(repository_name, image_name, tag)).await?;
+    if let Ok(digest) = digest.parse::<ContentDigest>() {
+        blobs
+            .delete(&tagged_key(repository_name, image_name, &digest, tag))
+            .await?;
+    }
+    Ok(true)
+}
+
+/// Delete every tag which points to the manifest of the digest, found through the index.
+pub async fn untag_manifest<B: BlobStorage>(
+    blobs: &B,
+    repository_name: &str,
+    image_name: &str,
+    digest: &ContentDigest,
+) -> Result<()> {
+    let prefix = tagged_prefix(repository_name, image_name, digest);
+    for key in list_all(blobs, &prefix).await? {
+        let tag = &key[prefix.len()..];
+        // The tag may have been moved to another manifest since it was indexed.
+        let target = find_tag(blobs, repository_name, image_name, tag).await?;
+        if target.map_or(false, |target| target == digest.to_string()) {
+            blobs.delete(&tag_key(repository_name, image_name, tag)).await?;
+        }
+        blobs.delete(&key).await?;
+    }
+    Ok(())
+}
+
+/// Add the descriptor of a manifest to the referrers of `subject`.
+pub async fn put_referrer<B: BlobStorage>(
+    blobs: &B,
+    repository_name: &str,
+    image_name: &str,
+    subject: &ContentDigest,
+    descriptor: &Descriptor,
+) -> Result<()> {
+    let key = referrer_key(repository_name, image_name, subject, &descriptor.digest);
+    blobs
+        .put(&key, serde_json::to_vec(descriptor)?, Metadata::default())
+        .await
+}
+
+/// Descriptors of every manifest which refers to `subject`.
+pub async fn list_referrers<B: BlobStorage>(
+    blobs: &B,
+    repository_name: &str,
+    image_name: &str,
+    subject: &ContentDigest,
+) -> Result<Vec<Descriptor>> {
+    let prefix = referrers_prefix(repository_name, image_name, subject);
+    let mut descriptors = Vec::new();
+    for key in list_all(blobs, &prefix).await? {
+        if let Some((_, body)) = blobs.get(&key).await? {
+            descriptors.push(serde_json::from_slice(&body)?);
+        }
+    }
+    Ok(descriptors)
+}
@@ -0,0 +1,180 @@
+use std::collections::HashMap;
+use worker::js_sys::{Array, Date, Object, Reflect};
+use worker::wasm_bindgen::{JsCast, JsValue};
+use worker::wasm_bindgen_futures::JsFuture;
+use worker::worker_sys::web_sys::ReadableStream;
+use worker::*;
+
+use super::{BlobStorage, Metadata, ObjectInfo};
+
+// `Object::size` and `Range` of `worker` are 32-bit, which can't address layers larger than 4 GiB,
+// so objects are read through the binding directly.
+//
+// `MultipartUpload` neither exposes its upload id nor accepts parts uploaded by another invocation,
+// so uploads which span several requests are created and completed through the binding directly as well.
+
+impl BlobStorage for Bucket {
+    type Stream = ReadableStream;
+
+    async fn head(&self, key: &str) -> Result<Option<ObjectInfo>> {
+        let object = JsFuture::from(binding(self).head(key.to_string())).await?;
+        if object.is_null() || object.is_undefined() {
+            return Ok(None);
+        }
+        Ok(Some(object_info(&object)?))
+    }
+
+    async fn get(&self, key: &str) -> Result<Option<(ObjectInfo, Vec<u8>)>> {
+        let object = match Bucket::get(self, key).execute().await? {
+            Some(object) => object,
+            None => return Ok(None),
+        };
+        let metadata = Metadata {
+            content_type: object.http_metadata().content_type,
+            custom: object.custom_metadata()?,
+        };
+        let body = match object.body() {
+            Some(body) => body.bytes().await?,
+            None => Vec::new(),
+        };
+        let info = ObjectInfo {
+            size: body.len() as u64,
+            uploaded: object.uploaded().as_millis() / 1000,
+            metadata,
+        };
+        Ok(Some((info, body)))
+    }
+
+    async fn stream(&self, key: &str, range: Option<(u64, u64)>) -> Result<Option<ReadableStream>> {
+        let options = Object::new();
+        if let Some((start, end)) = range {
+            let range = Object::new();
+            Reflect::set(&range, &"offset".into(), &JsValue::from_f64(start as f64))?;
+            Reflect::set(&range, &"length".into(), &JsValue::from_f64((end - start + 1) as f64))?;
+            Reflect::set(&options, &"range".into(), &range)?;
+        }
+
+        let object = JsFuture::from(binding(self).get(key.to_string(), options.into())).await?;
+        if object.is_null() || object.is_undefined() {
+            return Ok(None);
+        }
+        Ok(Some(Reflect::get(&object, &"body".into())?.unchecked_into()))
+    }
+
+    async fn put(&self, key: &str, value: Vec<u8>, metadata: Metadata) -> Result<()> {
+        let mut put = Bucket::put(self, key, value);
+        if metadata.content_type.is_some() {
+            put = put.http_metadata(HttpMetadata {
+                content_type: metadata.content_type,
+                ..Default::default()
+            });
+        }
+        if !metadata.custom.is_empty() {
+            put = put.custom_metdata(metadata.custom);
+        }
+        put.execute().await?;
+        Ok(())
+    }
+
+    async fn delete(&self, key: &str) -> Result<()> {
+        Bucket::delete(self, key).await
+    }
+
+    async fn list(&self, prefix: &str, start_after: Option<&str>, limit: usize) -> Result<(Vec<String>, bool)> {
+        // `startAfter` isn't exposed by `ListOptionsBuilder`, which only resumes from an opaque cursor.
+        let options = Object::new();
+        Reflect::set(&options, &"prefix".into(), &JsValue::from_str(prefix))?;
+        Reflect::set(&options, &"delimiter".into(), &JsValue::from_str("/"))?;
+        if let Some(start_after) = start_after {
+            Reflect::set(&options, &"startAfter".into(), &JsValue::from_str(start_after))?;
+        }
+
+        // Nested images count toward the limit as delimited prefixes, so a page may hold fewer keys than asked for.
+        let mut keys = Vec::new();
+        loop {
+            Reflect::set(
+                &options,
+                &"limit".into(),
+                &JsValue::from_f64((limit - keys.len()) as f64),
+            )?;
+            let objects = JsFuture::from(binding(self).list(options.clone().into())).await?;
+            let truncated = Reflect::get(&objects, &"truncated".into())?.is_truthy();
+            keys.extend(
+                Reflect::get(&objects, &"objects".into())?
+                    .unchecked_into::<Array>()
+                    .iter()
+                    .filter_map(|object| Reflect::get(&object, &"key".into()).ok())
+                    .filter_map(|key| key.as_string()),
+            );
+            if !truncated || keys.len() >= limit {
+                return Ok((keys, truncated));
+            }
+            Reflect::delete_property(&options, &"startAfter".into())?;
+            Reflect::set(&options, &"cursor".into(), &Reflect::get(&objects, &"cursor".into())?)?;
+        }
+    }
+
+    async fn create_multipart_upload(&self, key: &str) -> Result<String> {
+        let upload = JsFuture::from(binding(self).create_multipart_upload(key.to_string(), JsValue::UNDEFINED)).await?;
+        Ok(upload.unchecked_into::<worker_sys::R2MultipartUpload>().upload_id())
+    }
+
+    async fn upload_part(&self, key: &str, upload_id: &str, part_number: u16, part: Vec<u8>) -> Result<String> {
+        let uploaded = self
+            .resume_multipart_upload(key, upload_id)?
+            .upload_part(part_number, part)
+            .await?;
+        Ok(uploaded.etag())
+    }
+
+    async fn complete_multipart_upload(&self, key: &str, upload_id: &str, part_etags: &[String]) -> Result<()> {
+        let upload: worker_sys::R2MultipartUpload = binding(self)
+            .resume_multipart_upload(key.to_string(), upload_id.to_string())
+            .unchecked_into();
+
+        let mut parts = Vec::with_capacity(part_etags.len());
+        for (index, etag) in part_etags.iter().enumerate() {
+            let part = Object::new();
+            Reflect::set(&part, &"partNumber".into(), &JsValue::from(index as u16 + 1))?;
+            Reflect::set(&part, &"etag".into(), &JsValue::from_str(etag))?;
+            parts.push(part.into());
+        }
+        JsFuture::from(upload.complete(parts)).await?;
+        Ok(())
+    }
+
+    async fn abort_multipart_upload(&self, key: &str, upload_id: &str) -> Result<()> {
+        self.resume_multipart_upload(key, upload_id)?.abort().await
+    }
+}
+
+fn binding(bucket: &Bucket) -> &worker_sys::R2Bucket {
+    bucket.as_ref().unchecked_ref()
+}
+
+fn object_info(object: &JsValue) -> Result<ObjectInfo> {
+    let size = Reflect::get(object, &"size".into())?.as_f64().unwrap_or_default() as u64;
+    let uploaded = Reflect::get(object, &"uploaded".into())?
+        .dyn_into::<Date>()
+        .map_or(0, |uploaded| (uploaded.get_time() / 1000.0) as u64);
+    let content_type = Reflect::get(object, &"httpMetadata".into())
+        .and_then(|metadata| Reflect::get(&metadata, &"contentType".into()))
+        .ok()
+        .and_then(|content_type| content_type.as_string());
+
+    let mut custom = HashMap::new();
+    let custom_metadata = Reflect::get(object, &"customMetadata".into())?;
+    if custom_metadata.is_object() {
+        for entry in Object::entries(custom_metadata.unchecked_ref()).iter() {
+            let entry: Array = entry.unchecked_into();
+            if let (Some(key), Some(value)) = (entry.get(0).as_string(), entry.get(1).as_string()) {
+                custom.insert(key, value);
+            }
+        }
+    }
+    Ok(ObjectInfo {
+        size,
+        uploaded,
+        metadata: Metadata { content_type, custom },
+    })
+}
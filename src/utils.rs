@@ -10,3 +10,16 @@ cfg_if! {
         pub fn set_panic_hook() {}
     }
 }
+
+/// Whether the value matches the pattern, where `*` matches any sequence of characters, including none.
+pub fn glob_match(pattern: &str, value: &str) -> bool {
+    match pattern.split_once('*') {
+        None => pattern == value,
+        Some((prefix, rest)) => match value.strip_prefix(prefix) {
+            Some(value) => (0..=value.len())
+                .filter(|&i| value.is_char_boundary(i))
+                .any(|i| glob_match(rest, &value[i..])),
+            None => false,
+        },
+    }
+}